@@ -0,0 +1,472 @@
+//! Exclusive XML Canonicalization 1.0 (exc-c14n).
+//!
+//! Used to sign messages, and handy to compare envelopes or WSDLs without
+//! caring about attribute order, quoting or namespace declaration noise.
+
+use std::collections::HashMap;
+
+use sxd_document::parser;
+use sxd_document::dom::{ Attribute, ChildOfElement, ChildOfRoot, Comment, Document, Element };
+
+use crate::error::SoapError;
+
+pub const EXC_C14N:               &str = "http://www.w3.org/2001/10/xml-exc-c14n#";
+pub const EXC_C14N_WITH_COMMENTS: &str = "http://www.w3.org/2001/10/xml-exc-c14n#WithComments";
+
+/// Exclusive XML Canonicalization 1.0, with or without comments.
+///
+/// `inclusive_prefixes` is the `InclusiveNamespaces PrefixList`: prefixes
+/// whose declarations are rendered like inclusive C14N would, even when the
+/// element doesn't use them. `#default` stands for the default namespace.
+#[derive(Clone, Debug, Default)]
+pub struct C14n {
+    pub inclusive_prefixes: Vec<String>,
+    pub with_comments:      bool,
+}
+
+impl C14n {
+    pub fn new() -> C14n {
+        C14n::default()
+    }
+
+    pub fn with_prefixes(prefixes: &str) -> C14n {
+        C14n {
+            inclusive_prefixes: prefixes.split_whitespace()
+                .map(|p| p.to_string())
+                .collect(),
+            with_comments:      false,
+        }
+    }
+
+    /// Keeps comments in the output, as the `#WithComments` variant does.
+    pub fn comments(mut self, with_comments: bool) -> C14n {
+        self.with_comments = with_comments;
+        self
+    }
+
+    /// The algorithm URI for this variant, as used in `ds:Transform`.
+    pub fn algorithm(&self) -> &'static str {
+        if self.with_comments {
+            EXC_C14N_WITH_COMMENTS
+        } else {
+            EXC_C14N
+        }
+    }
+
+    /// Canonical form of a whole document. Comments and processing
+    /// instructions around the document element go on their own lines.
+    pub fn document(&self, document: &Document) -> String {
+        let mut output = String::new();
+        let mut after  = false;
+
+        for child in document.root().children() {
+            let mut node = String::new();
+
+            match child {
+                ChildOfRoot::Element(element) => {
+                    self.write_element(element, &HashMap::new(), &mut node);
+                    output.push_str(node.as_str());
+                    after = true;
+                    continue;
+                },
+                ChildOfRoot::Comment(comment) => {
+                    if !self.with_comments {
+                        continue;
+                    }
+
+                    write_comment(comment, &mut node);
+                },
+                ChildOfRoot::ProcessingInstruction(pi) => {
+                    write_pi(pi.target(), pi.value(), &mut node);
+                },
+            }
+
+            if after {
+                output.push('\n');
+                output.push_str(node.as_str());
+            } else {
+                output.push_str(node.as_str());
+                output.push('\n');
+            }
+        }
+
+        output
+    }
+
+    /// Parses `xml` and returns its canonical form, e.g. the output of
+    /// `soap::Response::to_xml_string`.
+    pub fn string(&self, xml: &str) -> Result<String, SoapError> {
+        match parser::parse(xml) {
+            Ok(package) => Ok(self.document(&package.as_document())),
+            Err(e)      => Err(SoapError::Malformed(format!("{:?}", e))),
+        }
+    }
+
+    /// Canonical form of `element` and its descendants, as a document
+    /// subset of which `element` is the apex.
+    pub fn element(&self, element: Element) -> String {
+        let mut output = String::new();
+        self.write_element(element, &HashMap::new(), &mut output);
+        output
+    }
+
+    fn write_element(&self, element: Element, rendered: &HashMap<String, String>, output: &mut String) {
+        let mut declarations: Vec<(String, String)> = vec![];
+
+        let declare = |prefix: String, uri: String, declarations: &mut Vec<(String, String)>| {
+            let current = rendered.get(&prefix).map(|u| u.as_str()).unwrap_or("");
+
+            if current != uri && !declarations.iter().any(|d| d.0 == prefix) {
+                declarations.push((prefix, uri));
+            }
+        };
+
+        // The element's own namespace is always visibly utilized.
+        let name = element.name();
+
+        match name.namespace_uri() {
+            Some(uri) => {
+                let prefix = element_prefix(element).unwrap_or_default();
+                declare(prefix, uri.to_string(), &mut declarations);
+            },
+            None => declare(String::new(), String::new(), &mut declarations),
+        }
+
+        let mut attributes: Vec<Attribute> = element.attributes();
+
+        for attr in attributes.iter() {
+            if let Some(uri) = attr.name().namespace_uri() {
+                if let Some(prefix) = attribute_prefix(element, *attr) {
+                    if prefix != "xml" {
+                        declare(prefix, uri.to_string(), &mut declarations);
+                    }
+                }
+            }
+        }
+
+        for prefix in self.inclusive_prefixes.iter() {
+            let prefix = if prefix == "#default" { "" } else { prefix.as_str() };
+
+            if let Some(uri) = in_scope(element, prefix) {
+                declare(prefix.to_string(), uri, &mut declarations);
+            }
+        }
+
+        declarations.sort_by(|a, b| a.0.cmp(&b.0));
+
+        attributes.sort_by(|a, b| {
+            let a_key = (a.name().namespace_uri().unwrap_or(""), a.name().local_part());
+            let b_key = (b.name().namespace_uri().unwrap_or(""), b.name().local_part());
+            a_key.cmp(&b_key)
+        });
+
+        let qname = qualified(element_prefix(element), name.local_part());
+
+        output.push('<');
+        output.push_str(qname.as_str());
+
+        let mut scope = rendered.clone();
+
+        for (prefix, uri) in declarations {
+            if prefix == "" {
+                output.push_str(" xmlns=\"");
+            } else {
+                output.push_str(" xmlns:");
+                output.push_str(prefix.as_str());
+                output.push_str("=\"");
+            }
+
+            output.push_str(escape_attribute(uri.as_str()).as_str());
+            output.push('"');
+
+            scope.insert(prefix, uri);
+        }
+
+        for attr in attributes {
+            let prefix = match attr.name().namespace_uri() {
+                Some(_) => attribute_prefix(element, attr),
+                None    => None,
+            };
+
+            output.push(' ');
+            output.push_str(qualified(prefix, attr.name().local_part()).as_str());
+            output.push_str("=\"");
+            output.push_str(escape_attribute(attr.value()).as_str());
+            output.push('"');
+        }
+
+        output.push('>');
+
+        for child in element.children() {
+            match child {
+                ChildOfElement::Element(elem) => self.write_element(elem, &scope, output),
+                ChildOfElement::Text(text)    => output.push_str(escape_text(text.text()).as_str()),
+                ChildOfElement::ProcessingInstruction(pi) => {
+                    write_pi(pi.target(), pi.value(), output);
+                },
+                ChildOfElement::Comment(comment) => {
+                    if self.with_comments {
+                        write_comment(comment, output);
+                    }
+                },
+            }
+        }
+
+        output.push_str("</");
+        output.push_str(qname.as_str());
+        output.push('>');
+    }
+}
+
+fn write_comment(comment: Comment, output: &mut String) {
+    output.push_str("<!--");
+    output.push_str(comment.text());
+    output.push_str("-->");
+}
+
+fn write_pi(target: &str, value: Option<&str>, output: &mut String) {
+    output.push_str("<?");
+    output.push_str(target);
+
+    if let Some(value) = value {
+        if value != "" {
+            output.push(' ');
+            output.push_str(value);
+        }
+    }
+
+    output.push_str("?>");
+}
+
+fn qualified(prefix: Option<String>, local: &str) -> String {
+    match prefix {
+        Some(ref prefix) if prefix != "" => format!("{}:{}", prefix, local),
+        _ => local.to_string(),
+    }
+}
+
+/// Prefix an element is written with. Elements in a namespace without a
+/// prefix are in the default namespace.
+fn element_prefix(element: Element) -> Option<String> {
+    let uri = element.name().namespace_uri()?;
+
+    match element.preferred_prefix() {
+        Some(prefix) => Some(prefix.to_string()),
+        None => match in_scope(element, "") {
+            Some(ref default) if default == uri => None,
+            _ => element.prefix_for_namespace_uri(uri, None).map(|p| p.to_string()),
+        },
+    }
+}
+
+fn attribute_prefix(element: Element, attr: Attribute) -> Option<String> {
+    let uri = attr.name().namespace_uri()?;
+
+    if uri == "http://www.w3.org/XML/1998/namespace" {
+        return Some(String::from("xml"));
+    }
+
+    match attr.preferred_prefix() {
+        Some(prefix) => Some(prefix.to_string()),
+        None         => element.prefix_for_namespace_uri(uri, None).map(|p| p.to_string()),
+    }
+}
+
+/// Namespace bound to `prefix` (`""` for the default one) at `element`.
+fn in_scope(element: Element, prefix: &str) -> Option<String> {
+    if prefix == "" {
+        return element.recursive_default_namespace_uri().map(|u| u.to_string());
+    }
+
+    element.namespace_uri_for_prefix(prefix).map(|u| u.to_string())
+}
+
+fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '&'  => escaped.push_str("&amp;"),
+            '<'  => escaped.push_str("&lt;"),
+            '>'  => escaped.push_str("&gt;"),
+            '\r' => escaped.push_str("&#xD;"),
+            _    => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn escape_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+
+    for c in value.chars() {
+        match c {
+            '&'  => escaped.push_str("&amp;"),
+            '<'  => escaped.push_str("&lt;"),
+            '"'  => escaped.push_str("&quot;"),
+            '\t' => escaped.push_str("&#x9;"),
+            '\n' => escaped.push_str("&#xA;"),
+            '\r' => escaped.push_str("&#xD;"),
+            _    => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sxd_document::Package;
+
+    // Examples from the Exclusive XML Canonicalization 1.0 and Canonical XML
+    // 1.0 recommendations, with the expected output they give.
+
+    fn apex(xml: &str, name: &str, c14n: C14n) -> String {
+        fn find<'d>(element: Element<'d>, name: &str) -> Option<Element<'d>> {
+            if element.name().local_part() == name {
+                return Some(element);
+            }
+
+            element.children().into_iter()
+                .filter_map(|child| child.element())
+                .filter_map(|child| find(child, name))
+                .next()
+        }
+
+        let package: Package = parser::parse(xml).unwrap();
+        let document = package.as_document();
+        let root     = document.root().children().into_iter()
+            .filter_map(|child| child.element())
+            .next()
+            .unwrap();
+
+        c14n.element(find(root, name).unwrap())
+    }
+
+    #[test]
+    fn leaves_out_unused_ancestor_namespaces() {
+        // Exclusive XML Canonicalization, section 2.2.
+        let first = concat!(
+            r#"<n0:local xmlns:n0="foo:bar" xmlns:n3="ftp://example.org">"#,
+            r#"<n1:elem2 xmlns:n1="http://example.net" xml:lang="en">"#,
+            r#"<n3:stuff xmlns:n3="ftp://example.org"/>"#,
+            r#"</n1:elem2>"#,
+            r#"</n0:local>"#,
+        );
+
+        let second = concat!(
+            r#"<n2:pdu xmlns:n1="http://example.com" xmlns:n2="http://foo.example" xml:lang="fr" xml:space="retain">"#,
+            r#"<n1:elem2 xmlns:n1="http://example.net" xml:lang="en">"#,
+            r#"<n3:stuff xmlns:n3="ftp://example.org"/>"#,
+            r#"</n1:elem2>"#,
+            r#"</n2:pdu>"#,
+        );
+
+        let expected = concat!(
+            r#"<n1:elem2 xmlns:n1="http://example.net" xml:lang="en">"#,
+            r#"<n3:stuff xmlns:n3="ftp://example.org"></n3:stuff>"#,
+            r#"</n1:elem2>"#,
+        );
+
+        assert_eq!(apex(first, "elem2", C14n::new()), expected);
+        assert_eq!(apex(second, "elem2", C14n::new()), expected);
+    }
+
+    #[test]
+    fn pushes_declarations_down_to_where_they_are_used() {
+        let xml = concat!(
+            r#"<a:root xmlns:a="urn:a" xmlns:b="urn:b" xmlns:c="urn:c">"#,
+            r#"<b:child><b:leaf c:attr="1" plain="2"/></b:child>"#,
+            r#"<b:child/>"#,
+            r#"</a:root>"#,
+        );
+
+        assert_eq!(C14n::new().string(xml).unwrap(), concat!(
+            r#"<a:root xmlns:a="urn:a">"#,
+            r#"<b:child xmlns:b="urn:b"><b:leaf xmlns:c="urn:c" plain="2" c:attr="1"></b:leaf></b:child>"#,
+            r#"<b:child xmlns:b="urn:b"></b:child>"#,
+            r#"</a:root>"#,
+        ));
+    }
+
+    #[test]
+    fn renders_inclusive_prefixes_where_they_are_in_scope() {
+        let xml = concat!(
+            r#"<root xmlns="urn:default" xmlns:b="urn:b" xmlns:c="urn:c">"#,
+            r#"<b:child><b:leaf c:attr="1"/></b:child>"#,
+            r#"</root>"#,
+        );
+
+        assert_eq!(C14n::with_prefixes("c").string(xml).unwrap(), concat!(
+            r#"<root xmlns="urn:default" xmlns:c="urn:c">"#,
+            r#"<b:child xmlns:b="urn:b"><b:leaf c:attr="1"></b:leaf></b:child>"#,
+            r#"</root>"#,
+        ));
+
+        // Only the apex renders them when canonicalizing a subset.
+        assert_eq!(apex(xml, "child", C14n::with_prefixes("#default c")),
+            r#"<b:child xmlns="urn:default" xmlns:b="urn:b" xmlns:c="urn:c"><b:leaf c:attr="1"></b:leaf></b:child>"#
+        );
+    }
+
+    // Canonical XML 1.0, example 3.1, without the document type declaration.
+    const OUTSIDE_DOCUMENT_ELEMENT: &str = concat!(
+        "<?xml version=\"1.0\"?>\n",
+        "\n",
+        "<?xml-stylesheet   href=\"doc.xsl\"\n",
+        "   type=\"text/xsl\"   ?>\n",
+        "\n",
+        "<doc>Hello, world!<!-- Comment 1 --></doc>\n",
+        "\n",
+        "<?pi-without-data     ?>\n",
+        "\n",
+        "<!-- Comment 2 -->\n",
+        "\n",
+        "<!-- Comment 3 -->\n",
+    );
+
+    #[test]
+    fn drops_comments_by_default() {
+        assert_eq!(C14n::new().string(OUTSIDE_DOCUMENT_ELEMENT).unwrap(), concat!(
+            "<?xml-stylesheet href=\"doc.xsl\"\n",
+            "   type=\"text/xsl\"   ?>\n",
+            "<doc>Hello, world!</doc>\n",
+            "<?pi-without-data?>",
+        ));
+    }
+
+    #[test]
+    fn keeps_comments_when_asked() {
+        assert_eq!(C14n::new().comments(true).string(OUTSIDE_DOCUMENT_ELEMENT).unwrap(), concat!(
+            "<?xml-stylesheet href=\"doc.xsl\"\n",
+            "   type=\"text/xsl\"   ?>\n",
+            "<doc>Hello, world!<!-- Comment 1 --></doc>\n",
+            "<?pi-without-data?>\n",
+            "<!-- Comment 2 -->\n",
+            "<!-- Comment 3 -->",
+        ));
+    }
+
+    #[test]
+    fn escapes_text_and_attributes() {
+        // Canonical XML 1.0, example 3.4, in part.
+        let xml = concat!(
+            "<doc>",
+            "<text>First line&#x0d;&#10;Second line</text>",
+            "<compute><![CDATA[value>\"0\" && value<\"10\" ?\"valid\":\"error\"]]></compute>",
+            "<norm attr=' &apos;   &#x20;&#13;&#xa;&#9;   &apos; '/>",
+            "</doc>",
+        );
+
+        assert_eq!(C14n::new().string(xml).unwrap(), concat!(
+            "<doc>",
+            "<text>First line&#xD;\nSecond line</text>",
+            "<compute>value&gt;\"0\" &amp;&amp; value&lt;\"10\" ?\"valid\":\"error\"</compute>",
+            "<norm attr=\" '    &#xD;&#xA;&#x9;   ' \"></norm>",
+            "</doc>",
+        ));
+    }
+}
@@ -1,20 +1,32 @@
 use std::{ io, str };
 
-extern crate hyper;
-
 #[derive(Debug)]
 pub enum SoapError {
     NotFound,
-    Http(hyper::error::Error),
+    Http(hyper::Error),
     Io(io::Error),
     Encoding(str::Utf8Error),
     Malformed(String),
     Unexpected(String),
+    Timeout,
+    Tls(String),
+    TooLarge,
 }
               
-impl From<hyper::error::Error> for SoapError {
-    fn from(other: hyper::error::Error) -> SoapError {
+impl From<hyper::Error> for SoapError {
+    fn from(other: hyper::Error) -> SoapError {
         SoapError::Http(other)
     }
 }
 
+impl From<io::Error> for SoapError {
+    fn from(other: io::Error) -> SoapError {
+        SoapError::Io(other)
+    }
+}
+
+impl From<str::Utf8Error> for SoapError {
+    fn from(other: str::Utf8Error) -> SoapError {
+        SoapError::Encoding(other)
+    }
+}
@@ -1,6 +1,18 @@
+// The code base predates most of these lints and keeps its own style:
+// `Struct { field: field }`, `match &x { &A(ref a) => .. }` and friends.
+#![allow(
+    clippy::comparison_to_empty,
+    clippy::match_like_matches_macro,
+    clippy::match_ref_pats,
+    clippy::needless_borrowed_reference,
+    clippy::redundant_field_names,
+    clippy::single_char_add_str,
+)]
+
 #[macro_use]
-extern crate macros;
+extern crate maplit;
 
+pub mod c14n;
 #[macro_use]
 pub mod error;
 pub mod soap;
@@ -0,0 +1,474 @@
+use std::collections::HashMap;
+use std::sync::{ Arc, Mutex };
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+use futures::future::{ self, BoxFuture, FutureExt };
+use md5::Md5;
+use rand::RngCore;
+use sha2::{ Digest as Hash, Sha256 };
+
+use crate::service::{ Handler, Request, Response };
+
+/// Identity established by an `Authenticator`. Handlers find it in
+/// `Request::extensions`, and SOAP operations through `Context::principal`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Principal {
+    pub name:   String,
+    pub scheme: String,
+}
+
+/// Checks the credentials carried by a request.
+pub trait Authenticator: Send + Sync + 'static {
+    /// The authenticated principal, or `None` if the request has no valid
+    /// credentials, in which case it is answered with a `401`.
+    fn authenticate<'a>(&'a self, request: &'a Request)
+        -> BoxFuture<'a, Option<Principal>>;
+
+    /// Value of the `WWW-Authenticate` header sent along with a `401`.
+    fn challenge(&self) -> String;
+
+    /// `challenge` for a `request` that was turned down, for schemes that
+    /// tell clients why, like Digest's `stale=true`.
+    fn challenge_for(&self, _request: &Request) -> String {
+        self.challenge()
+    }
+}
+
+/// Wraps `handler` so it only runs for requests `auth` accepts.
+pub fn guard(auth: Arc<dyn Authenticator>, handler: Handler) -> Handler {
+    Arc::new(move |mut request: Request| {
+        let auth    = auth.clone();
+        let handler = handler.clone();
+
+        async move {
+            match auth.authenticate(&request).await {
+                Some(principal) => {
+                    request.extensions.insert(principal);
+                    handler(request).await
+                },
+                None => {
+                    let mut response = Response::with_status(401);
+                    response.header.insert("WWW-Authenticate".to_string(), auth.challenge_for(&request));
+                    response
+                },
+            }
+        }.boxed()
+    })
+}
+
+/// Checks a user name and password, for `Basic`.
+type Verify = Box<dyn Fn(&str, &str) -> bool + Send + Sync>;
+
+/// Looks up the password of a user name, for `Digest`.
+type Password = Box<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// HTTP Basic authentication (RFC 7617). Only use it over TLS.
+pub struct Basic {
+    realm:  String,
+    verify: Verify,
+}
+
+impl Basic {
+    /// `verify` gets the user name and password and says whether they are
+    /// valid. Compare secrets with `constant_time_eq`.
+    pub fn new<F>(realm: &str, verify: F) -> Basic
+        where F: Fn(&str, &str) -> bool + Send + Sync + 'static
+    {
+        Basic {
+            realm:  realm.to_string(),
+            verify: Box::new(verify),
+        }
+    }
+
+    /// Accepts the given user names and clear-text passwords.
+    pub fn with_users(realm: &str, users: HashMap<String, String>) -> Basic {
+        Basic::new(realm, move |user, pass| match users.get(user) {
+            Some(password) => constant_time_eq(password.as_bytes(), pass.as_bytes()),
+            None           => false,
+        })
+    }
+}
+
+impl Authenticator for Basic {
+    fn authenticate<'a>(&'a self, request: &'a Request)
+        -> BoxFuture<'a, Option<Principal>>
+    {
+        let principal = credentials(request, "Basic")
+            .and_then(|encoded| base64::decode(encoded.trim()).ok())
+            .and_then(|decoded| String::from_utf8(decoded).ok())
+            .and_then(|decoded| {
+                let mut split = decoded.splitn(2, ':');
+
+                match (split.next(), split.next()) {
+                    (Some(user), Some(pass)) if (self.verify)(user, pass) =>
+                        Some(Principal {
+                            name:   user.to_string(),
+                            scheme: String::from("Basic"),
+                        }),
+                    _ => None,
+                }
+            });
+
+        future::ready(principal).boxed()
+    }
+
+    fn challenge(&self) -> String {
+        format!("Basic realm=\"{}\", charset=\"UTF-8\"", self.realm)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DigestAlgorithm {
+    Md5,
+    Sha256,
+}
+
+impl DigestAlgorithm {
+    fn name(&self) -> &'static str {
+        match self {
+            &DigestAlgorithm::Md5    => "MD5",
+            &DigestAlgorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    fn hash(&self, data: &str) -> String {
+        match self {
+            &DigestAlgorithm::Md5    => hex(&Md5::digest(data.as_bytes())),
+            &DigestAlgorithm::Sha256 => hex(&Sha256::digest(data.as_bytes())),
+        }
+    }
+}
+
+/// HTTP Digest authentication (RFC 7616) with `qop=auth`.
+///
+/// Each nonce carries its creation time and a keyed hash of it, and is
+/// accepted until `nonce_ttl` seconds have passed. Clients with an expired
+/// nonce but the right password are told so with `stale=true`. The last
+/// `nc` seen for each nonce in use is kept, and requests have to raise it,
+/// so a captured header can't be replayed.
+pub struct Digest {
+    realm:     String,
+    algorithm: DigestAlgorithm,
+    nonce_ttl: u64,
+    secret:    String,
+    password:  Password,
+    counts:    Mutex<HashMap<String, (u64, u64)>>,
+}
+
+/// What `Digest::check` made of a request.
+enum Outcome {
+    Valid(Principal),
+    Stale,
+    Invalid,
+}
+
+impl Digest {
+    /// `password` looks up the clear-text password of a user name.
+    pub fn new<F>(realm: &str, algorithm: DigestAlgorithm, password: F) -> Digest
+        where F: Fn(&str) -> Option<String> + Send + Sync + 'static
+    {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+
+        Digest {
+            realm:     realm.to_string(),
+            algorithm: algorithm,
+            nonce_ttl: 300,
+            secret:    hex(&secret),
+            password:  Box::new(password),
+            counts:    Mutex::new(hashmap!{}),
+        }
+    }
+
+    pub fn nonce_ttl(mut self, seconds: u64) -> Digest {
+        self.nonce_ttl = seconds;
+        self
+    }
+
+    fn nonce(&self, timestamp: u64) -> String {
+        let mac = self.algorithm.hash(
+            format!("{}:{}:{}", timestamp, self.realm, self.secret).as_str());
+
+        base64::encode(format!("{}:{}", timestamp, mac))
+    }
+
+    /// Creation time of `nonce`, if this server issued it.
+    fn nonce_time(&self, nonce: &str) -> Option<u64> {
+        let decoded = String::from_utf8(base64::decode(nonce).ok()?).ok()?;
+        let timestamp: u64 = decoded.split(':').next()?.parse().ok()?;
+
+        if constant_time_eq(self.nonce(timestamp).as_bytes(), nonce.as_bytes()) {
+            Some(timestamp)
+        } else {
+            None
+        }
+    }
+
+    fn check(&self, request: &Request) -> Outcome {
+        match self.verify(request) {
+            Some(outcome) => outcome,
+            None          => Outcome::Invalid,
+        }
+    }
+
+    fn verify(&self, request: &Request) -> Option<Outcome> {
+        let params = parse_params(credentials(request, "Digest")?);
+
+        let user     = params.get("username")?;
+        let realm    = params.get("realm")?;
+        let nonce    = params.get("nonce")?;
+        let uri      = params.get("uri")?;
+        let response = params.get("response")?;
+        let nc       = params.get("nc")?;
+        let cnonce   = params.get("cnonce")?;
+        let qop      = params.get("qop")?;
+
+        let algorithm = params.get("algorithm").map(|a| a.as_str()).unwrap_or("MD5");
+
+        if realm != &self.realm || qop != "auth" || algorithm != self.algorithm.name() {
+            return None;
+        }
+
+        let issued = self.nonce_time(nonce)?;
+        let count  = u64::from_str_radix(nc, 16).ok()?;
+
+        // The digest covers the URI in the header, which has to be the one
+        // actually requested.
+        if uri.split('?').next() != Some(request.path.as_str()) {
+            return None;
+        }
+
+        let password = (self.password)(user)?;
+
+        let ha1 = self.algorithm.hash(format!("{}:{}:{}", user, realm, password).as_str());
+        let ha2 = self.algorithm.hash(format!("{}:{}", request.method, uri).as_str());
+
+        let expected = self.algorithm.hash(
+            format!("{}:{}:{}:{}:{}:{}", ha1, nonce, nc, cnonce, qop, ha2).as_str());
+
+        if !constant_time_eq(expected.as_bytes(), response.as_bytes()) {
+            return None;
+        }
+
+        let now = now();
+
+        if now > issued + self.nonce_ttl {
+            return Some(Outcome::Stale);
+        }
+
+        let mut counts = self.counts.lock().unwrap();
+        counts.retain(|_, &mut (expires, _)| expires >= now);
+
+        let last = counts.get(nonce.as_str()).map(|&(_, last)| last).unwrap_or(0);
+
+        if count <= last {
+            return None;
+        }
+
+        counts.insert(nonce.clone(), (issued + self.nonce_ttl, count));
+
+        Some(Outcome::Valid(Principal {
+            name:   user.clone(),
+            scheme: String::from("Digest"),
+        }))
+    }
+}
+
+impl Authenticator for Digest {
+    fn authenticate<'a>(&'a self, request: &'a Request)
+        -> BoxFuture<'a, Option<Principal>>
+    {
+        let principal = match self.check(request) {
+            Outcome::Valid(principal) => Some(principal),
+            _                         => None,
+        };
+
+        future::ready(principal).boxed()
+    }
+
+    fn challenge(&self) -> String {
+        format!(
+            "Digest realm=\"{}\", qop=\"auth\", algorithm={}, nonce=\"{}\"",
+            self.realm, self.algorithm.name(), self.nonce(now())
+        )
+    }
+
+    fn challenge_for(&self, request: &Request) -> String {
+        match self.check(request) {
+            Outcome::Stale => format!("{}, stale=true", self.challenge()),
+            _              => self.challenge(),
+        }
+    }
+}
+
+/// Credentials of the `Authorization` header if they use `scheme`.
+fn credentials<'a>(request: &'a Request, scheme: &str) -> Option<&'a str> {
+    let header = request.header.get("authorization")?;
+    let split  = header.find(' ')?;
+
+    if header[..split].eq_ignore_ascii_case(scheme) {
+        Some(header[split + 1..].trim())
+    } else {
+        None
+    }
+}
+
+/// Parses `key=value, key="quoted, value"` lists.
+fn parse_params(input: &str) -> HashMap<String, String> {
+    let mut params = hashmap!{};
+    let mut chars  = input.chars().peekable();
+
+    loop {
+        while let Some(&c) = chars.peek() {
+            if c == ',' || c.is_whitespace() { chars.next(); } else { break; }
+        }
+
+        let key: String = chars.by_ref().take_while(|&c| c != '=').collect();
+
+        if key.is_empty() {
+            break;
+        }
+
+        let mut value = String::new();
+
+        if chars.peek() == Some(&'"') {
+            chars.next();
+
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => if let Some(c) = chars.next() { value.push(c) },
+                    '"'  => break,
+                    _    => value.push(c),
+                }
+            }
+        } else {
+            while let Some(&c) = chars.peek() {
+                if c == ',' { break; }
+                value.push(c);
+                chars.next();
+            }
+        }
+
+        params.insert(key.trim().to_lowercase(), value.trim().to_string());
+    }
+
+    params
+}
+
+/// Compares secrets in time that only depends on their length.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+
+    fn digest() -> Digest {
+        Digest::new("test", DigestAlgorithm::Sha256, |user| match user {
+            "alice" => Some(String::from("secret")),
+            _       => None,
+        })
+    }
+
+    fn request(digest: &Digest, nonce: &str, nc: &str, password: &str) -> Request {
+        let algorithm = digest.algorithm;
+
+        let ha1 = algorithm.hash(format!("alice:test:{}", password).as_str());
+        let ha2 = algorithm.hash("POST:/soap");
+        let response = algorithm.hash(format!("{}:{}:{}:abc:auth:{}", ha1, nonce, nc, ha2).as_str());
+
+        let mut request = Request {
+            method: String::from("POST"),
+            path:   String::from("/soap"),
+            ..Request::default()
+        };
+
+        request.header.insert(String::from("authorization"), format!(
+            "Digest username=\"alice\", realm=\"test\", nonce=\"{}\", uri=\"/soap\", \
+             response=\"{}\", nc={}, cnonce=\"abc\", qop=auth, algorithm=SHA-256",
+            nonce, response, nc));
+
+        request
+    }
+
+    fn accepts(auth: &dyn Authenticator, request: &Request) -> bool {
+        block_on(auth.authenticate(request)).is_some()
+    }
+
+    #[test]
+    fn digest_accepts_the_right_password() {
+        let digest = digest();
+        let nonce  = digest.nonce(now());
+
+        assert!(accepts(&digest, &request(&digest, nonce.as_str(), "00000001", "secret")));
+        assert!(!accepts(&digest, &request(&digest, nonce.as_str(), "00000002", "wrong")));
+    }
+
+    #[test]
+    fn digest_rejects_replayed_counts() {
+        let digest = digest();
+        let nonce  = digest.nonce(now());
+        let first  = request(&digest, nonce.as_str(), "00000001", "secret");
+
+        assert!(accepts(&digest, &first));
+        assert!(!accepts(&digest, &first));
+        assert!(accepts(&digest, &request(&digest, nonce.as_str(), "00000002", "secret")));
+    }
+
+    #[test]
+    fn digest_marks_expired_nonces_stale() {
+        let digest = digest().nonce_ttl(10);
+        let nonce  = digest.nonce(now() - 60);
+
+        let stale = request(&digest, nonce.as_str(), "00000001", "secret");
+        assert!(!accepts(&digest, &stale));
+        assert!(digest.challenge_for(&stale).ends_with("stale=true"));
+
+        let wrong = request(&digest, nonce.as_str(), "00000001", "wrong");
+        assert!(!digest.challenge_for(&wrong).contains("stale"));
+    }
+
+    #[test]
+    fn digest_rejects_forged_nonces() {
+        let digest = digest();
+        let nonce  = base64::encode(format!("{}:forged", now()));
+
+        assert!(!accepts(&digest, &request(&digest, nonce.as_str(), "00000001", "secret")));
+    }
+
+    #[test]
+    fn basic_checks_users() {
+        let basic = Basic::with_users("test", hashmap!{ String::from("bob") => String::from("pw") });
+
+        let mut request = Request::default();
+        request.header.insert(String::from("authorization"), format!("Basic {}", base64::encode("bob:pw")));
+        assert!(accepts(&basic, &request));
+
+        request.header.insert(String::from("authorization"), format!("Basic {}", base64::encode("bob:px")));
+        assert!(!accepts(&basic, &request));
+    }
+
+    #[test]
+    fn constant_time_eq_compares_bytes() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+    }
+}
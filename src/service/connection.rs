@@ -0,0 +1,181 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::{ self, FutureExt };
+use hyper::{ Body, Request as HttpRequest, Response as HttpResponse };
+use hyper::server::conn::Http;
+use hyper::service::service_fn;
+use tokio::io::{ AsyncRead, AsyncWrite };
+use tokio::net::TcpListener;
+use tokio::sync::{ mpsc, oneshot, watch };
+use tokio_rustls::TlsAcceptor;
+
+use crate::error::*;
+use crate::service::error::*;
+use crate::service::{ InFlight, Request };
+use crate::service::auth::guard;
+use crate::service::middleware::{ Middleware, Next };
+use crate::service::router::Match;
+use crate::service::service::Routes;
+
+/// How long a client gets to finish the TLS handshake. Connections count
+/// towards the drain from the start, so a stalled handshake would otherwise
+/// hold up shutdown forever.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Everything a connection needs to answer requests.
+pub struct Pipeline {
+    pub routes:        Routes,
+    pub middlewares:   Vec<Arc<dyn Middleware>>,
+    pub max_body_size: usize,
+}
+
+/// Accept loop. Runs until `signal` fires, then stops accepting and waits
+/// for every open connection to finish its current request.
+pub async fn serve(
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    pipeline: Arc<Pipeline>,
+    signal:   oneshot::Receiver<()>
+) -> Result<(), SoapError> {
+    // Each connection, and each request's `InFlight`, holds a clone of
+    // `draining`; `drained` yields `None` once all of them are gone.
+    // Dropping `closing` (i.e. aborting this task) makes the connections
+    // give up on draining.
+    let (draining, mut drained) = mpsc::channel::<()>(1);
+    let (closing, closed)       = watch::channel(false);
+
+    let signal = async move {
+        // A dropped handle detaches the server instead of stopping it.
+        if signal.await.is_err() {
+            future::pending::<()>().await;
+        }
+    };
+
+    tokio::pin!(signal);
+
+    loop {
+        let (stream, remote_addr) = tokio::select! {
+            _ = &mut signal => break,
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                // Usually transient (e.g. out of file descriptors).
+                Err(_) => continue,
+            },
+        };
+
+        let connection = Connection {
+            pipeline:    pipeline.clone(),
+            remote_addr: remote_addr,
+            closed:      closed.clone(),
+            in_flight:   InFlight::new(draining.clone()),
+        };
+
+        let acceptor = acceptor.clone();
+
+        tokio::spawn(async move {
+            match acceptor {
+                Some(acceptor) => {
+                    let handshake = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream));
+
+                    let stream = match handshake.await {
+                        Ok(Ok(stream)) => stream,
+                        _              => return,
+                    };
+
+                    let certificates = match stream.get_ref().1.peer_certificates() {
+                        Some(certs) => certs.iter().map(|c| c.0.clone()).collect(),
+                        None        => vec![],
+                    };
+
+                    connection.run(stream, certificates).await
+                },
+                None => connection.run(stream, vec![]).await,
+            }
+        });
+    }
+
+    drop(listener);
+    drop(draining);
+
+    let _ = closing.send(true);
+    let _ = drained.recv().await;
+
+    Ok(())
+}
+
+struct Connection {
+    pipeline:    Arc<Pipeline>,
+    remote_addr: SocketAddr,
+    closed:      watch::Receiver<bool>,
+    in_flight:   InFlight,
+}
+
+impl Connection {
+    async fn run<S>(mut self, stream: S, certificates: Vec<Vec<u8>>)
+        where S: AsyncRead + AsyncWrite + Unpin + Send + 'static
+    {
+        let pipeline     = self.pipeline.clone();
+        let remote_addr  = self.remote_addr;
+        let certificates = Arc::new(certificates);
+        let in_flight    = self.in_flight.clone();
+
+        let service = service_fn(move |request| {
+            dispatch(pipeline.clone(), remote_addr, certificates.clone(), in_flight.clone(), request)
+                .map(Ok::<_, Infallible>)
+        });
+
+        let conn = Http::new().serve_connection(stream, service);
+        tokio::pin!(conn);
+
+        tokio::select! {
+            _ = &mut conn => return,
+            _ = self.closed.changed() => conn.as_mut().graceful_shutdown(),
+        }
+
+        // The second change only comes from the server giving up on the
+        // drain deadline.
+        tokio::select! {
+            _ = &mut conn => (),
+            _ = self.closed.changed() => (),
+        }
+    }
+}
+
+async fn dispatch(
+    pipeline:     Arc<Pipeline>,
+    remote_addr:  SocketAddr,
+    certificates: Arc<Vec<Vec<u8>>>,
+    in_flight:    InFlight,
+    request:      HttpRequest<Body>
+) -> HttpResponse<Body> {
+    let (handler, params) = {
+        let routes = pipeline.routes.lock().unwrap();
+        let auth   = routes.find_authenticator(request.uri().path());
+
+        match routes.find(request.method().as_str(), request.uri().path()) {
+            Match::Found(handler, params) => match auth {
+                Some(auth) => (guard(auth, handler), params),
+                None       => (handler, params),
+            },
+            Match::MethodNotAllowed(allowed) => (method_not_allowed_handler(allowed), hashmap!{}),
+            Match::NotFound                  => (error_handler(404), hashmap!{}),
+        }
+    };
+
+    let response = match Request::from_http(request, pipeline.max_body_size).await {
+        Ok(mut request) => {
+            request.params            = params;
+            request.remote_addr       = Some(remote_addr);
+            request.peer_certificates = (*certificates).clone();
+            request.extensions.insert(in_flight);
+            Next::new(&pipeline.middlewares, &handler).run(request).await
+        },
+        Err(SoapError::TooLarge) => error_handler(413)(Request::default()).await,
+        Err(_)                   => error_handler(400)(Request::default()).await,
+    };
+
+    From::from(response)
+}
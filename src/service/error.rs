@@ -1,12 +1,21 @@
-use service::Request;
-use service::Response;
-
-pub fn error_handler(code: u16)
-    -> Box<FnMut(Request) -> Response + Send + 'static>
-{
-    Box::new(move |_request: Request| -> Response {
-        let mut response = Response::default();
-        response.header.insert("status-code".to_string(), code.to_string());
-        response
+use std::sync::Arc;
+
+use futures::future::{ self, FutureExt };
+
+use crate::service::{ Handler, Request, Response };
+
+pub fn error_handler(code: u16) -> Handler {
+    Arc::new(move |_request: Request| {
+        future::ready(Response::with_status(code)).boxed()
+    })
+}
+
+pub fn method_not_allowed_handler(allowed: Vec<String>) -> Handler {
+    let allow = allowed.join(", ");
+
+    Arc::new(move |_request: Request| {
+        let mut response = Response::with_status(405);
+        response.header.insert("Allow".to_string(), allow.clone());
+        future::ready(response).boxed()
     })
 }
@@ -0,0 +1,88 @@
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::sync::{ mpsc, oneshot };
+use tokio::task::JoinHandle;
+
+use crate::error::*;
+
+/// Handle to a running server, returned by `Service::start`.
+///
+/// Dropping the handle detaches the server, which then keeps running until
+/// the runtime is shut down.
+pub struct Handle {
+    local_addr: SocketAddr,
+    shutdown:   Option<oneshot::Sender<()>>,
+    task:       JoinHandle<Result<(), SoapError>>,
+}
+
+impl Handle {
+    pub fn new(
+        local_addr: SocketAddr,
+        shutdown:   oneshot::Sender<()>,
+        task:       JoinHandle<Result<(), SoapError>>
+    ) -> Handle {
+        Handle {
+            local_addr: local_addr,
+            shutdown:   Some(shutdown),
+            task:       task,
+        }
+    }
+
+    /// Address the listener is actually bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting new connections. Requests already in flight are
+    /// allowed to finish; use `join` or `shutdown_timeout` to wait on them.
+    pub fn shutdown(&mut self) {
+        if let Some(sender) = self.shutdown.take() {
+            let _ = sender.send(());
+        }
+    }
+
+    /// Waits until the server stops, either because of an error or because
+    /// `shutdown` was called.
+    pub async fn join(self) -> Result<(), SoapError> {
+        match self.task.await {
+            Ok(result) => result,
+            Err(e)     => Err(SoapError::Unexpected(e.to_string())),
+        }
+    }
+
+    /// Triggers a graceful shutdown and waits for in-flight requests to
+    /// drain. Connections still open after `deadline` are dropped and
+    /// `SoapError::Timeout` is returned.
+    pub async fn shutdown_timeout(mut self, deadline: Duration)
+        -> Result<(), SoapError>
+    {
+        self.shutdown();
+
+        match tokio::time::timeout(deadline, &mut self.task).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e))     => Err(SoapError::Unexpected(e.to_string())),
+            Err(_)         => {
+                self.task.abort();
+                Err(SoapError::Timeout)
+            },
+        }
+    }
+}
+
+/// Keeps a graceful shutdown waiting for as long as it, or a clone of it,
+/// is alive. Every request carries one in its extensions; handlers that
+/// finish their work in the background hold on to a clone until they are
+/// done, so that `Handle::shutdown_timeout` waits for them too.
+#[derive(Clone, Debug)]
+pub struct InFlight {
+    _draining: mpsc::Sender<()>,
+}
+
+impl InFlight {
+    pub(crate) fn new(draining: mpsc::Sender<()>) -> InFlight {
+        InFlight {
+            _draining: draining,
+        }
+    }
+}
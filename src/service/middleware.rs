@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use crate::service::{ Handler, Request, Response };
+
+/// Hook that sits between the HTTP server and the route handlers.
+///
+/// Middleware runs in the order it was added, for every request, including
+/// the ones that end up as a 404 or 405. Each one may change the request,
+/// answer it directly without calling `next`, or change the response on the
+/// way back. Data for later middleware or handlers can be attached to
+/// `Request::extensions`.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>)
+        -> BoxFuture<'a, Response>;
+}
+
+/// The rest of the chain, ending in the route handler.
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    handler:     &'a Handler,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middlewares: &'a [Arc<dyn Middleware>], handler: &'a Handler) -> Next<'a> {
+        Next {
+            middlewares: middlewares,
+            handler:     handler,
+        }
+    }
+
+    pub fn run(self, request: Request) -> BoxFuture<'a, Response> {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => first.handle(request, Next::new(rest, self.handler)),
+            None                => (self.handler)(request),
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use rand::RngCore;
+
+use crate::error::SoapError;
+
+/// One body part of a `multipart/related` message.
+#[derive(Clone, Debug, Default)]
+pub struct MimePart {
+    /// Part headers, with lowercase names.
+    pub header:  HashMap<String, String>,
+    pub content: Bytes,
+}
+
+impl MimePart {
+    pub fn new<C: Into<Bytes>>(content_id: &str, content_type: &str, content: C) -> MimePart {
+        let mut header = hashmap!{};
+        header.insert(String::from("content-id"), format!("<{}>", content_id));
+        header.insert(String::from("content-type"), content_type.to_string());
+        header.insert(String::from("content-transfer-encoding"), String::from("binary"));
+
+        MimePart {
+            header:  header,
+            content: content.into(),
+        }
+    }
+
+    /// `Content-ID` without its angle brackets.
+    pub fn content_id(&self) -> &str {
+        self.header.get("content-id")
+            .map(|id| id.trim().trim_start_matches('<').trim_end_matches('>'))
+            .unwrap_or("")
+    }
+
+    pub fn content_type(&self) -> &str {
+        self.header.get("content-type")
+            .map(|t| t.as_str())
+            .unwrap_or("application/octet-stream")
+    }
+}
+
+/// Media type of a `Content-Type` value, lowercase and without parameters.
+pub fn essence(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_ascii_lowercase()
+}
+
+/// Parameter `name` of a `Content-Type` value, unquoted.
+pub fn param(content_type: &str, name: &str) -> Option<String> {
+    content_type.split(';').skip(1)
+        .filter_map(|param| {
+            let mut split = param.splitn(2, '=');
+            let key   = split.next()?.trim();
+            let value = split.next()?.trim();
+
+            if key.eq_ignore_ascii_case(name) {
+                Some(value.trim_matches('"').to_string())
+            } else {
+                None
+            }
+        })
+        .next()
+}
+
+/// A boundary that is very unlikely to show up in the parts.
+pub fn boundary() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("MIMEBoundary_{}", hex)
+}
+
+/// Splits a multipart body into its parts. The preamble and epilogue are
+/// ignored, and base64 transfer encoding is undone. Other parts share
+/// `body` rather than being copied out of it.
+pub fn parse(body: &Bytes, boundary: &str) -> Result<Vec<MimePart>, SoapError> {
+    let delimiter = format!("--{}", boundary).into_bytes();
+    let next      = format!("\r\n--{}", boundary).into_bytes();
+    let malformed = || SoapError::Malformed(String::from("Malformed multipart body"));
+
+    let mut parts = vec![];
+    let mut start = find(body, &delimiter, 0).ok_or_else(malformed)? + delimiter.len();
+
+    loop {
+        if body[start..].starts_with(b"--") {
+            break;
+        }
+
+        // Rest of the delimiter line, usually just CRLF.
+        let line_end = find(body, b"\r\n", start).ok_or_else(malformed)?;
+
+        let end  = find(body, &next, line_end + 2).ok_or_else(malformed)?;
+        let part = &body[line_end + 2..end];
+
+        // A part may have no headers at all.
+        let (head, content) = if part.starts_with(b"\r\n") {
+            (&part[..0], &part[2..])
+        } else {
+            match find(part, b"\r\n\r\n", 0) {
+                Some(split) => (&part[..split], &part[split + 4..]),
+                None        => return Err(malformed()),
+            }
+        };
+
+        let mut header: HashMap<String, String> = hashmap!{};
+        let head = String::from_utf8_lossy(head);
+
+        for line in head.split("\r\n") {
+            let mut split = line.splitn(2, ':');
+
+            if let (Some(name), Some(value)) = (split.next(), split.next()) {
+                header.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+            }
+        }
+
+        let encoding = header.get("content-transfer-encoding")
+            .map(|e| e.to_ascii_lowercase())
+            .unwrap_or_default();
+
+        let content = if encoding == "base64" {
+            let text: String = String::from_utf8_lossy(content).split_whitespace().collect();
+
+            match base64::decode(text) {
+                Ok(content) => Bytes::from(content),
+                Err(_)      => return Err(malformed()),
+            }
+        } else {
+            body.slice_ref(content)
+        };
+
+        parts.push(MimePart {
+            header:  header,
+            content: content,
+        });
+
+        start = end + next.len();
+    }
+
+    Ok(parts)
+}
+
+/// Joins `parts` into a multipart body delimited by `boundary`.
+pub fn write(parts: &[MimePart], boundary: &str) -> Vec<u8> {
+    let mut body = vec![];
+
+    for part in parts {
+        body.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+
+        let mut names: Vec<&String> = part.header.keys().collect();
+        names.sort();
+
+        for name in names {
+            body.extend_from_slice(
+                format!("{}: {}\r\n", display_name(name), part.header[name]).as_bytes());
+        }
+
+        body.extend_from_slice(b"\r\n");
+        body.extend_from_slice(&part.content);
+        body.extend_from_slice(b"\r\n");
+    }
+
+    body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    body
+}
+
+/// `content-id` as `Content-ID`, the way most MIME readers expect it.
+fn display_name(name: &str) -> String {
+    name.split('-')
+        .map(|word| match word {
+            "id" => String::from("ID"),
+            _    => {
+                let mut chars = word.chars();
+
+                match chars.next() {
+                    Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str(),
+                    None        => String::new(),
+                }
+            },
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+fn find(haystack: &[u8], needle: &[u8], from: usize) -> Option<usize> {
+    if from > haystack.len() {
+        return None;
+    }
+
+    haystack[from..].windows(needle.len())
+        .position(|window| window == needle)
+        .map(|position| position + from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_what_it_writes() {
+        let parts = vec![
+            MimePart::new("root@test", "text/xml", "<a/>"),
+            MimePart::new("file=1@test", "application/octet-stream", vec![0u8, 13, 10, 45, 45, 255]),
+        ];
+
+        let body   = Bytes::from(write(&parts, "b0undary"));
+        let parsed = parse(&body, "b0undary").unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].content_id(), "root@test");
+        assert_eq!(parsed[0].content_type(), "text/xml");
+        assert_eq!(parsed[0].content, Bytes::from("<a/>"));
+        assert_eq!(parsed[1].content_id(), "file=1@test");
+        assert_eq!(parsed[1].content, parts[1].content);
+    }
+
+    #[test]
+    fn parts_share_the_body() {
+        let body   = Bytes::from(write(&[MimePart::new("file@test", "image/png", "PNG")], "b"));
+        let parsed = parse(&body, "b").unwrap();
+
+        let start = body.as_ptr() as usize;
+        let part  = parsed[0].content.as_ptr() as usize;
+
+        assert!(part >= start && part < start + body.len());
+    }
+
+    #[test]
+    fn undoes_base64_and_skips_preamble_and_epilogue() {
+        let body = Bytes::from(concat!(
+            "preamble\r\n",
+            "--b\r\n",
+            "Content-ID: <file@test>\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "aGVs\r\nbG8=\r\n",
+            "--b\r\n",
+            "\r\n",
+            "no headers\r\n",
+            "--b--\r\n",
+            "epilogue"));
+
+        let parsed = parse(&body, "b").unwrap();
+
+        assert_eq!(parsed.len(), 2);
+        assert_eq!(parsed[0].content, Bytes::from("hello"));
+        assert_eq!(parsed[1].content_id(), "");
+        assert_eq!(parsed[1].content, Bytes::from("no headers"));
+    }
+
+    #[test]
+    fn rejects_malformed_bodies() {
+        assert!(parse(&Bytes::from("no delimiter"), "b").is_err());
+        assert!(parse(&Bytes::from("--b\r\nContent-ID: <x>\r\n\r\nnever closed"), "b").is_err());
+        assert!(parse(&Bytes::from("--b\r\nContent-Transfer-Encoding: base64\r\n\r\n!!\r\n--b--"), "b").is_err());
+    }
+
+    #[test]
+    fn reads_content_type_parameters() {
+        let content_type = "Multipart/Related; type=\"application/xop+xml\"; boundary=\"MIME_b\"";
+
+        assert_eq!(essence(content_type), "multipart/related");
+        assert_eq!(param(content_type, "boundary"), Some(String::from("MIME_b")));
+        assert_eq!(param(content_type, "start"), None);
+    }
+}
@@ -1,8 +1,21 @@
+mod auth;
+mod connection;
 mod error;
+mod handle;
+mod middleware;
+pub mod mime;
 mod request;
 mod response;
+mod router;
+#[allow(clippy::module_inception)]
 mod service;
+mod tls;
 
+pub use self::auth::{ constant_time_eq, Authenticator, Basic, Digest, DigestAlgorithm, Principal };
+pub use self::handle::{ Handle, InFlight };
+pub use self::middleware::{ Middleware, Next };
+pub use self::mime::MimePart;
 pub use self::request::Request;
 pub use self::response::Response;
-pub use self::service::Service;
+pub use self::service::{ Handler, Service, DEFAULT_MAX_BODY_SIZE };
+pub use self::tls::TlsConfig;
@@ -1,36 +1,167 @@
 use std::collections::HashMap;
-use std::io::Read;
+use std::net::SocketAddr;
+use std::str;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ SystemTime, UNIX_EPOCH };
 
-extern crate hyper;
-use self::hyper::server::Request as HttpRequest;
+use bytes::{ Bytes, BytesMut };
+use hyper::{ Body, Request as HttpRequest };
+use hyper::body::HttpBody;
+use hyper::http::Extensions;
+
+use crate::error::*;
+use crate::service::mime::{ self, MimePart };
 
 #[derive(Default, Debug)]
 pub struct Request {
+    pub method:  String,
+    pub path:    String,
+    pub query:   HashMap<String, String>,
+    pub params:  HashMap<String, String>,
     pub header:  HashMap<String, String>,
     pub content: String,
+
+    /// The other parts of a `multipart/related` request, whose root part
+    /// is in `content`.
+    pub attachments: Vec<MimePart>,
+
+    /// Taken from the `X-Request-Id` header when the client sends one,
+    /// generated otherwise.
+    pub request_id:  String,
+    pub remote_addr: Option<SocketAddr>,
+
+    /// Typed values attached by middleware for later middleware and handlers.
+    pub extensions: Extensions,
+
+    /// DER-encoded certificate chain the client presented over TLS, leaf
+    /// first. Empty for plain HTTP or when client auth is not required.
+    pub peer_certificates: Vec<Vec<u8>>,
 }
 
 impl Request {
     pub fn new(header: HashMap<String, String>, content: String) -> Request {
         Request {
+            method:  String::from("POST"),
+            path:    String::from("/"),
+            query:   hashmap!{},
+            params:  hashmap!{},
             header:  header,
             content: content,
+            attachments: vec![],
+            request_id:  next_request_id(),
+            remote_addr: None,
+            extensions: Extensions::new(),
+            peer_certificates: vec![],
         }
     }
-}
 
-impl<'a, 'b> From<HttpRequest<'a, 'b>> for Request {
-    fn from(mut other: HttpRequest<'a, 'b>) -> Request {
-        let mut request = Request::default();
+    /// Whether `key` was passed in the query string, ignoring case, as in
+    /// `?wsdl` or `?WSDL`.
+    pub fn has_query(&self, key: &str) -> bool {
+        self.query.keys().any(|k| k.eq_ignore_ascii_case(key))
+    }
+
+    /// Reads the whole body of an incoming hyper request without blocking
+    /// the worker thread. Bodies longer than `max_body_size` fail with
+    /// `SoapError::TooLarge`.
+    pub async fn from_http(other: HttpRequest<Body>, max_body_size: usize) -> Result<Request, SoapError> {
+        let (parts, body) = other.into_parts();
+
+        let mut request = Request {
+            method:     parts.method.as_str().to_string(),
+            path:       parts.uri.path().to_string(),
+            extensions: parts.extensions,
+            ..Request::default()
+        };
 
-        for h in other.headers.iter() {
-            request.header.insert(h.name().to_string(), h.value_string());
+        if let Some(query) = parts.uri.query() {
+            for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+                request.query.insert(key.into_owned(), value.into_owned());
+            }
         }
 
-        request.content = String::new();
-        let _ = other.read_to_string(&mut request.content);
+        for (name, value) in parts.headers.iter() {
+            request.header.insert(
+                name.as_str().to_string(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            );
+        }
+
+        request.request_id = match request.header.get("x-request-id") {
+            Some(id) => id.clone(),
+            None     => next_request_id(),
+        };
+
+        let declared = request.header.get("content-length")
+            .and_then(|length| length.trim().parse::<u64>().ok());
+
+        if declared.map(|length| length > max_body_size as u64).unwrap_or(false) {
+            return Err(SoapError::TooLarge);
+        }
+
+        let bytes = read_body(body, max_body_size).await?;
+
+        let content_type = request.header.get("content-type").cloned().unwrap_or_default();
+
+        if mime::essence(content_type.as_str()) != "multipart/related" {
+            request.content = str::from_utf8(&bytes)?.to_string();
+            return Ok(request);
+        }
 
-        request
+        let boundary = match mime::param(content_type.as_str(), "boundary") {
+            Some(boundary) => boundary,
+            None => return Err(SoapError::Malformed(String::from("Multipart request without a boundary"))),
+        };
+
+        let mut parts = mime::parse(&bytes, boundary.as_str())?;
+
+        // The root is the `start` part, or else the first one.
+        let start = mime::param(content_type.as_str(), "start")
+            .map(|id| id.trim_start_matches('<').trim_end_matches('>').to_string());
+
+        let root = match start {
+            Some(id) => parts.iter().position(|part| part.content_id() == id),
+            None     => Some(0),
+        };
+
+        let root = match root {
+            Some(root) if root < parts.len() => parts.remove(root),
+            _ => return Err(SoapError::Malformed(String::from("Multipart request without a root part"))),
+        };
+
+        request.content     = str::from_utf8(&root.content)?.to_string();
+        request.attachments = parts;
+
+        Ok(request)
     }
 }
 
+/// Reads `body` whole, giving up as soon as it is longer than `limit`:
+/// chunked bodies have no Content-Length to check beforehand.
+async fn read_body(mut body: Body, limit: usize) -> Result<Bytes, SoapError> {
+    let mut buffer = BytesMut::new();
+
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+
+        if buffer.len() + chunk.len() > limit {
+            return Err(SoapError::TooLarge);
+        }
+
+        buffer.extend_from_slice(&chunk);
+    }
+
+    Ok(buffer.freeze())
+}
+
+/// Unique enough for correlating logs: current time plus a counter.
+fn next_request_id() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+    let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+    let now   = SystemTime::now().duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+
+    format!("{:x}-{:04x}", now, count & 0xffff)
+}
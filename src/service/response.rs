@@ -1,15 +1,19 @@
 use std::collections::HashMap;
 
-extern crate hyper;
-use self::hyper::server::Response as HttpResponse;
-use self::hyper::status::StatusCode;
+use hyper::{ Body, Response as HttpResponse, StatusCode };
+use hyper::header::{ HeaderName, HeaderValue };
 
-use error::*;
+use crate::service::mime::{ self, MimePart };
 
 #[derive(Default)]
 pub struct Response {
     pub header:  HashMap<String, String>,
     pub content: String,
+
+    /// Sent along with `content` as a `multipart/related` body when not
+    /// empty. `content` becomes the root part, and the `content-type`
+    /// header its type.
+    pub attachments: Vec<MimePart>,
 }
 
 impl Response {
@@ -17,28 +21,73 @@ impl Response {
         Response {
             header:  header,
             content: content,
+            attachments: vec![],
         }
     }
 
-    pub fn write_response(&self, mut response: HttpResponse) -> Result<(), SoapError> {
-        for (key, value) in (&self.header).iter() {
+    /// Empty response with the given HTTP status code.
+    pub fn with_status(code: u16) -> Response {
+        let mut response = Response::default();
+        response.header.insert("status-code".to_string(), code.to_string());
+        response
+    }
+}
+
+impl From<Response> for HttpResponse<Body> {
+    fn from(other: Response) -> HttpResponse<Body> {
+        let mut header = other.header;
+
+        let body = if other.attachments.is_empty() {
+            Body::from(other.content)
+        } else {
+            let root_type = header.remove("content-type")
+                .unwrap_or_else(|| String::from("text/xml; charset=utf-8"));
+
+            let boundary = mime::boundary();
+            let root_id  = "root.message@webservices";
+
+            let mut parts = vec![MimePart::new(root_id, root_type.as_str(), other.content.into_bytes())];
+            parts.extend(other.attachments);
+
+            let mut content_type = format!(
+                "multipart/related; type=\"{}\"; boundary=\"{}\"; start=\"<{}>\"",
+                mime::essence(root_type.as_str()), boundary, root_id);
+
+            // XOP packages also say what the root part really is.
+            if let Some(start_info) = mime::param(root_type.as_str(), "type") {
+                content_type.push_str(format!("; start-info=\"{}\"", start_info).as_str());
+            }
+
+            header.insert(String::from("content-type"), content_type);
+
+            Body::from(mime::write(&parts, boundary.as_str()))
+        };
+
+        let mut response = HttpResponse::new(body);
+
+        for (key, value) in header.iter() {
             match key.as_str() {
                 "status-code" => {
-                    *response.status_mut() = StatusCode::Unregistered(value.parse().unwrap());
+                    let status = value.parse().ok()
+                        .and_then(|code| StatusCode::from_u16(code).ok());
+
+                    if let Some(status) = status {
+                        *response.status_mut() = status;
+                    }
                 },
                 _ => {
-                    response.headers_mut().set_raw(
-                        key.clone(),
-                        vec![value.clone().into_bytes()]
-                    );
+                    let name  = HeaderName::from_bytes(key.as_bytes());
+                    let value = HeaderValue::from_str(value.as_str());
+
+                    // Headers hyper can't represent are dropped rather than
+                    // failing the whole response.
+                    if let (Ok(name), Ok(value)) = (name, value) {
+                        response.headers_mut().insert(name, value);
+                    }
                 }
             }
         }
 
-        match response.send(self.content.as_bytes()) {
-            Ok(_)  => Ok(()),
-            Err(e) => Err(SoapError::Io(e)),
-        }
+        response
     }
 }
-
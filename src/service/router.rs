@@ -0,0 +1,338 @@
+use std::collections::{ BTreeMap, BTreeSet, HashMap };
+use std::sync::Arc;
+
+use percent_encoding::percent_decode_str;
+
+use crate::service::Handler;
+use crate::service::auth::Authenticator;
+
+/// Maps a path and an HTTP method to a handler.
+///
+/// Patterns are made of `/`-separated segments, each one either a literal,
+/// a `{name}` parameter matching exactly one segment, or a trailing `*` (or
+/// `{*name}`) matching any number of remaining segments, including none.
+/// Empty segments are ignored, so `/a/` and `/a` are the same path, and
+/// path segments are percent-decoded before matching.
+///
+/// When several patterns match, the most specific one wins: segments are
+/// compared left to right, literals beat parameters and parameters beat
+/// wildcards, so `/tenants/admin` beats `/tenants/{id}` beats `/tenants/*`.
+///
+/// Authenticators are attached to patterns the same way, independently of
+/// handlers, so `/admin/*` can protect every route below it.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+struct Route {
+    pattern:  String,
+    segments: Vec<Segment>,
+    methods:  Methods,
+    auth:     Option<Arc<dyn Authenticator>>,
+}
+
+#[derive(Default)]
+struct Methods {
+    any:      Option<Entry>,
+    handlers: BTreeMap<String, Entry>,
+}
+
+/// A handler and the names it gave to the parameters of its pattern, since
+/// `/a/{x}` and `/a/{y}` share one route.
+#[derive(Clone)]
+struct Entry {
+    handler: Handler,
+    names:   Vec<String>,
+}
+
+enum Segment {
+    Literal(String),
+    Param(String),
+    Wildcard(String),
+}
+
+pub enum Match {
+    Found(Handler, HashMap<String, String>),
+    MethodNotAllowed(Vec<String>),
+    NotFound,
+}
+
+impl Router {
+    /// Registers `handler` for `path`. With no method it answers any method
+    /// not claimed by a more specific route.
+    pub fn insert(&mut self, method: Option<&str>, path: &str, handler: Handler) {
+        let handler = Entry {
+            handler: handler,
+            names:   parse(path).into_iter().filter_map(|segment| segment.name()).collect(),
+        };
+
+        let methods = &mut self.route(path).methods;
+
+        match method {
+            Some(method) => {
+                methods.handlers.insert(method.to_uppercase(), handler);
+            },
+            None => methods.any = Some(handler),
+        }
+    }
+
+    /// Requires requests to `path` to pass `auth` before reaching their
+    /// handler.
+    pub fn set_authenticator(&mut self, path: &str, auth: Arc<dyn Authenticator>) {
+        self.route(path).auth = Some(auth);
+    }
+
+    /// Authenticator of the most specific pattern matching `path`.
+    pub fn find_authenticator(&self, path: &str) -> Option<Arc<dyn Authenticator>> {
+        let path = split(path);
+
+        self.routes.iter()
+            .filter(|route| route.auth.is_some() && route.capture(&path).is_some())
+            .min_by_key(|route| route.rank())
+            .and_then(|route| route.auth.clone())
+    }
+
+    pub fn find(&self, method: &str, path: &str) -> Match {
+        let path = split(path);
+
+        let mut matches: Vec<(Vec<u8>, &Route, Vec<String>)> = self.routes.iter()
+            .filter(|route| !route.methods.is_empty())
+            .filter_map(|route| {
+                route.capture(&path).map(|params| (route.rank(), route, params))
+            })
+            .collect();
+
+        if matches.is_empty() {
+            return Match::NotFound;
+        }
+
+        // Stable sort, so equally specific patterns keep insertion order.
+        matches.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut allowed = BTreeSet::new();
+
+        for (_, route, values) in matches {
+            if let Some(entry) = route.methods.get(method) {
+                let params = entry.names.into_iter().zip(values).collect();
+                return Match::Found(entry.handler, params);
+            }
+
+            allowed.extend(route.methods.handlers.keys().cloned());
+        }
+
+        Match::MethodNotAllowed(allowed.into_iter().collect())
+    }
+}
+
+impl Router {
+    fn route(&mut self, path: &str) -> &mut Route {
+        let segments = parse(path);
+        let pattern  = normalize(&segments);
+
+        let index = match self.routes.iter().position(|r| r.pattern == pattern) {
+            Some(index) => index,
+            None => {
+                self.routes.push(Route {
+                    pattern:  pattern,
+                    segments: segments,
+                    methods:  Methods::default(),
+                    auth:     None,
+                });
+
+                self.routes.len() - 1
+            },
+        };
+
+        &mut self.routes[index]
+    }
+}
+
+impl Route {
+    /// Values of the parameters and wildcard of this route, in order.
+    fn capture(&self, path: &[String]) -> Option<Vec<String>> {
+        let mut values = vec![];
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                &Segment::Wildcard(_) => {
+                    values.push(path[i.min(path.len())..].join("/"));
+                    return Some(values);
+                },
+                &Segment::Literal(ref literal) => {
+                    if path.get(i) != Some(literal) {
+                        return None;
+                    }
+                },
+                &Segment::Param(_) => match path.get(i) {
+                    Some(value) => values.push(value.clone()),
+                    None        => return None,
+                },
+            }
+        }
+
+        if path.len() == self.segments.len() {
+            Some(values)
+        } else {
+            None
+        }
+    }
+
+    fn rank(&self) -> Vec<u8> {
+        self.segments.iter().map(|segment| match segment {
+            &Segment::Literal(_)  => 0,
+            &Segment::Param(_)    => 1,
+            &Segment::Wildcard(_) => 2,
+        }).collect()
+    }
+}
+
+impl Methods {
+    fn is_empty(&self) -> bool {
+        self.any.is_none() && self.handlers.is_empty()
+    }
+
+    fn get(&self, method: &str) -> Option<Entry> {
+        match self.handlers.get(method) {
+            Some(entry) => Some(entry.clone()),
+            None        => self.any.clone(),
+        }
+    }
+}
+
+impl Segment {
+    fn name(self) -> Option<String> {
+        match self {
+            Segment::Literal(_)     => None,
+            Segment::Param(name)    => Some(name),
+            Segment::Wildcard(name) => Some(name),
+        }
+    }
+}
+
+/// Non-empty, percent-decoded segments of a request path. Decoding comes
+/// after splitting, so an encoded `/` stays inside its segment.
+fn split(path: &str) -> Vec<String> {
+    path.split('/')
+        .filter(|s| !s.is_empty())
+        .map(|s| percent_decode_str(s).decode_utf8_lossy().into_owned())
+        .collect()
+}
+
+fn parse(path: &str) -> Vec<Segment> {
+    let mut segments = vec![];
+
+    for segment in path.split('/').filter(|s| !s.is_empty()) {
+        if segment == "*" {
+            segments.push(Segment::Wildcard(String::from("*")));
+            break;
+        }
+
+        if segment.starts_with("{*") && segment.ends_with('}') {
+            segments.push(Segment::Wildcard(segment[2..segment.len() - 1].to_string()));
+            break;
+        }
+
+        if segment.starts_with('{') && segment.ends_with('}') {
+            segments.push(Segment::Param(segment[1..segment.len() - 1].to_string()));
+        } else {
+            segments.push(Segment::Literal(segment.to_string()));
+        }
+    }
+
+    segments
+}
+
+/// Canonical form of a pattern, so `/a/{x}` and `/a/{y}/` share methods.
+fn normalize(segments: &[Segment]) -> String {
+    let mut pattern = String::new();
+
+    for segment in segments {
+        pattern.push('/');
+        pattern.push_str(match segment {
+            &Segment::Literal(ref literal) => literal.as_str(),
+            &Segment::Param(_)             => "{}",
+            &Segment::Wildcard(_)          => "*",
+        });
+    }
+
+    if pattern.is_empty() {
+        pattern.push('/');
+    }
+
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+    use futures::future::FutureExt;
+
+    use crate::service::{ Request, Response };
+
+    fn handler(name: &'static str) -> Handler {
+        Arc::new(move |_| {
+            futures::future::ready(Response::new(hashmap!{}, String::from(name))).boxed()
+        })
+    }
+
+    fn found(router: &Router, method: &str, path: &str) -> (String, HashMap<String, String>) {
+        match router.find(method, path) {
+            Match::Found(handler, params) => (block_on(handler(Request::default())).content, params),
+            _ => panic!("no route for {} {}", method, path),
+        }
+    }
+
+    #[test]
+    fn most_specific_pattern_wins() {
+        let mut router = Router::default();
+        router.insert(None, "/tenants/*", handler("wildcard"));
+        router.insert(None, "/tenants/{id}", handler("param"));
+        router.insert(None, "/tenants/admin", handler("literal"));
+
+        assert_eq!(found(&router, "GET", "/tenants/admin").0, "literal");
+        assert_eq!(found(&router, "GET", "/tenants/42").0, "param");
+        assert_eq!(found(&router, "GET", "/tenants/42/users").0, "wildcard");
+        assert_eq!(found(&router, "GET", "/tenants/42/users").1["*"], "42/users");
+    }
+
+    #[test]
+    fn methods_keep_their_own_parameter_names() {
+        let mut router = Router::default();
+        router.insert(Some("GET"), "/a/{x}", handler("get"));
+        router.insert(Some("POST"), "/a/{y}", handler("post"));
+
+        let (name, params) = found(&router, "GET", "/a/1");
+        assert_eq!((name.as_str(), params.get("x").map(|s| s.as_str())), ("get", Some("1")));
+
+        let (name, params) = found(&router, "POST", "/a/2");
+        assert_eq!((name.as_str(), params.get("y").map(|s| s.as_str())), ("post", Some("2")));
+        assert!(!params.contains_key("x"));
+
+        match router.find("DELETE", "/a/1") {
+            Match::MethodNotAllowed(allowed) => assert_eq!(allowed, vec!["GET", "POST"]),
+            _ => panic!("expected 405"),
+        }
+    }
+
+    #[test]
+    fn segments_are_percent_decoded() {
+        let mut router = Router::default();
+        router.insert(None, "/files/{name}", handler("file"));
+        router.insert(None, "/hello world", handler("space"));
+
+        assert_eq!(found(&router, "GET", "/files/a%2Fb%20c").1["name"], "a/b c");
+        assert_eq!(found(&router, "GET", "/hello%20world").0, "space");
+    }
+
+    #[test]
+    fn unknown_paths_are_not_found() {
+        let mut router = Router::default();
+        router.insert(None, "/a/{x}", handler("a"));
+
+        assert!(match router.find("GET", "/a") { Match::NotFound => true, _ => false });
+        assert!(match router.find("GET", "/a/1/2") { Match::NotFound => true, _ => false });
+    }
+}
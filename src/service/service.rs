@@ -1,29 +1,41 @@
-use std::collections::HashMap;
 use std::fmt::{ Debug, Error, Formatter };
+use std::future::Future;
+use std::net::{ self, SocketAddr };
 use std::string::ToString;
 use std::sync::{ Arc, Mutex };
 
-extern crate hyper;
-use self::hyper::server::{ 
-    Request as HttpRequest,
-    Response as HttpResponse,
-    Server as HttpServer,
-};
-use self::hyper::uri::RequestUri;
+use futures::future::{ BoxFuture, FutureExt };
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
 
-use error::*;
-use service::error::*;
-use service::{ Request, Response };
+use crate::error::*;
+use crate::service::auth::Authenticator;
+use crate::service::connection::{ self, Pipeline };
+use crate::service::middleware::Middleware;
+use crate::service::router::Router;
+use crate::service::{ Handle, Request, Response, TlsConfig };
+
+/// A route handler. Handlers run on the tokio runtime and may be called
+/// concurrently, so they must be `Fn` and return a future.
+pub type Handler = Arc<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync + 'static>;
+
+pub(crate) type Routes = Arc<Mutex<Router>>;
+
+/// Largest request body read unless `Service::set_max_body_size` says
+/// otherwise: 16 MiB.
+pub const DEFAULT_MAX_BODY_SIZE: usize = 16 * 1024 * 1024;
 
 pub struct Service {
-    host_name: String,
-    bind_addr: String,
-    port:      u16,
-    ssl:       bool,
-    routes: Arc<Mutex<HashMap<
-        String,
-        Box<FnMut(Request) -> Response + Send + 'static>
-    >>>,
+    host_name:  String,
+    bind_addr:  String,
+    port:       u16,
+    ssl:        bool,
+    routes:     Routes,
+    middleware: Vec<Arc<dyn Middleware>>,
+    tls:        Option<TlsConfig>,
+    max_body:   usize,
+    listener:   Option<net::TcpListener>,
+    local_addr: Option<SocketAddr>,
 }
 
 impl Service {
@@ -33,14 +45,20 @@ impl Service {
 
     pub fn new_with_bind(host: &str, bind: &str, port: u16) -> Service {
         Service {
-            host_name: host.to_string(),
-            bind_addr: bind.to_string(),
-            port:      port,
-            ssl:       false,
-            routes:    Arc::new(Mutex::new(HashMap::new())),
+            host_name:  host.to_string(),
+            bind_addr:  bind.to_string(),
+            port:       port,
+            ssl:        false,
+            routes:     Arc::new(Mutex::new(Router::default())),
+            middleware: vec![],
+            tls:        None,
+            max_body:   DEFAULT_MAX_BODY_SIZE,
+            listener:   None,
+            local_addr: None,
         }
     }
 
+    #[allow(clippy::should_implement_trait)]
     pub fn default() -> Service {
         Service::new("localhost", 80)
     }
@@ -52,11 +70,20 @@ impl Service {
             String::from("http://")
         };
 
-        uri.push_str(self.host_name.as_str());
+        uri.push_str(bracketed(self.host_name.as_str()).as_str());
+
+        // Once bound, the real port wins over the configured one, which
+        // may have been 0.
+        let port = match self.local_addr {
+            Some(addr) => addr.port(),
+            None       => self.port,
+        };
+
+        let default_port = if self.ssl { 443 } else { 80 };
 
-        if self.port != 80 {
+        if port != default_port {
             uri.push_str(":");
-            uri.push_str(self.port.to_string().as_str());
+            uri.push_str(port.to_string().as_str());
         }
 
         uri.push_str("/");
@@ -64,49 +91,130 @@ impl Service {
     }
 
     pub fn get_bind(&self) -> String {
-        let mut bind = self.bind_addr.clone();
+        let mut bind = bracketed(self.bind_addr.as_str());
         bind.push_str(":");
         bind.push_str(self.port.to_string().as_str());
         bind
     }
 
-    pub fn add_route<
-        F: FnMut(Request) -> Response + Send + 'static
-    >(&mut self, route: &str, handler: F)
+    /// Address the server is listening on, once `bind` or `start` has been
+    /// called.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// Binds the listener without starting to serve requests, so the real
+    /// address is known (e.g. when binding to port 0) before building
+    /// anything that depends on `get_uri`. Calling it again before `start`
+    /// is a no-op.
+    pub fn bind(&mut self) -> Result<SocketAddr, SoapError> {
+        if let (Some(_), Some(addr)) = (&self.listener, self.local_addr) {
+            return Ok(addr);
+        }
+
+        let host     = self.bind_addr.trim_start_matches('[').trim_end_matches(']');
+        let listener = net::TcpListener::bind((host, self.port))?;
+        listener.set_nonblocking(true)?;
+
+        let addr = listener.local_addr()?;
+
+        self.listener   = Some(listener);
+        self.local_addr = Some(addr);
+
+        Ok(addr)
+    }
+
+    /// Routes every method on `route` to `handler`. Routes match on the
+    /// path only; the query string is available in `Request::query`.
+    ///
+    /// `route` may capture segments with `{name}` or mount a whole subtree
+    /// with a trailing `*`; captured values end up in `Request::params`.
+    /// See `Router` for how overlapping routes are resolved.
+    pub fn add_route<F, R>(&mut self, route: &str, handler: F)
+        where F: Fn(Request) -> R + Send + Sync + 'static,
+              R: Future<Output = Response> + Send + 'static
     {
         let mut routes = self.routes.lock().unwrap();
-        routes.insert(route.to_string(), Box::new(handler));
-    }
-
-    pub fn start(&mut self) -> Result<(), SoapError> {
-        let http  = try!(HttpServer::http(self.get_bind().as_str()));
-        let mutex = self.routes.clone();
-
-        let handle = http.handle(
-            move |request: HttpRequest, response: HttpResponse| {
-                let route = {
-                    match request.uri {
-                        RequestUri::AbsolutePath(ref path) => path.clone(),
-                        _ => String::new(),
-                    }
-                };
-
-                let mut e404    = error_handler(404);
-                let mut routes  = mutex.lock().unwrap();
-                let mut handler = match routes.get_mut(route.as_str()) {
-                    Some(handler) => handler,
-                    None          => &mut e404,
-                };
-
-                let res = handler(From::from(request));
-                let _   = res.write_response(response);
-            }
+        routes.insert(None, route, Arc::new(move |request| {
+            handler(request).boxed()
+        }));
+    }
+
+    /// Routes only `method` on `route` to `handler`. Other methods get a
+    /// `405 Method Not Allowed` listing the registered ones.
+    pub fn add_route_with_method<F, R>(&mut self, method: &str, route: &str, handler: F)
+        where F: Fn(Request) -> R + Send + Sync + 'static,
+              R: Future<Output = Response> + Send + 'static
+    {
+        let mut routes = self.routes.lock().unwrap();
+        routes.insert(Some(method), route, Arc::new(move |request| {
+            handler(request).boxed()
+        }));
+    }
+
+    /// Requires requests to `route` to authenticate with `auth`. `route`
+    /// is a pattern like the ones given to `add_route`, and does not need
+    /// a handler of its own: `/admin/*` protects every route under
+    /// `/admin`. The most specific pattern decides.
+    pub fn set_authenticator<A: Authenticator>(&mut self, route: &str, auth: A) {
+        let mut routes = self.routes.lock().unwrap();
+        routes.set_authenticator(route, Arc::new(auth));
+    }
+
+    /// Appends `middleware` to the chain every request goes through before
+    /// reaching its handler.
+    pub fn add_middleware<M: Middleware>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Serves HTTPS with the given certificates instead of plain HTTP.
+    pub fn set_tls(&mut self, tls: TlsConfig) {
+        self.ssl = true;
+        self.tls = Some(tls);
+    }
+
+    /// Answers requests whose body is larger than `bytes` with a `413
+    /// Payload Too Large`, without reading more of it than that.
+    pub fn set_max_body_size(&mut self, bytes: usize) {
+        self.max_body = bytes;
+    }
+
+    /// Binds the listener and spawns the server onto the current tokio
+    /// runtime. The returned handle is used to stop and join it.
+    pub async fn start(&mut self) -> Result<Handle, SoapError> {
+        // Load certificates first so a bad path fails before binding.
+        let acceptor = match self.tls {
+            Some(ref tls) => Some(tls.acceptor()?),
+            None          => None,
+        };
+
+        self.bind()?;
+
+        let listener   = TcpListener::from_std(self.listener.take().unwrap())?;
+        let local_addr = listener.local_addr()?;
+
+        let (shutdown, signal) = oneshot::channel::<()>();
+
+        let pipeline = Arc::new(Pipeline {
+            routes:        self.routes.clone(),
+            middlewares:   self.middleware.clone(),
+            max_body_size: self.max_body,
+        });
+
+        let task = tokio::spawn(
+            connection::serve(listener, acceptor, pipeline, signal)
         );
 
-        match handle {
-            Ok(_)  => Ok(()),
-            Err(e) => Err(From::from(e)),
-        }
+        Ok(Handle::new(local_addr, shutdown, task))
+    }
+}
+
+/// Wraps IPv6 literals in brackets so they can be followed by a port.
+fn bracketed(host: &str) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]", host)
+    } else {
+        host.to_string()
     }
 }
 
@@ -0,0 +1,97 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::sync::Arc;
+
+use rustls_pemfile::Item;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{ Certificate, PrivateKey, RootCertStore, ServerConfig };
+use tokio_rustls::rustls::server::AllowAnyAuthenticatedClient;
+
+use crate::error::*;
+
+/// TLS settings for a service. Everything is read from PEM files when the
+/// server starts.
+#[derive(Clone, Debug)]
+pub struct TlsConfig {
+    pub cert_chain:  String,
+    pub private_key: String,
+    pub client_ca:   Option<String>,
+}
+
+impl TlsConfig {
+    pub fn new(cert_chain: &str, private_key: &str) -> TlsConfig {
+        TlsConfig {
+            cert_chain:  cert_chain.to_string(),
+            private_key: private_key.to_string(),
+            client_ca:   None,
+        }
+    }
+
+    /// Requires every client to present a certificate signed by one of the
+    /// CAs in `client_ca`. Connections without one are refused during the
+    /// handshake.
+    pub fn require_client_cert(mut self, client_ca: &str) -> TlsConfig {
+        self.client_ca = Some(client_ca.to_string());
+        self
+    }
+
+    pub fn acceptor(&self) -> Result<TlsAcceptor, SoapError> {
+        let certs = read_certificates(self.cert_chain.as_str())?;
+        let key   = read_private_key(self.private_key.as_str())?;
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+
+        let builder = match self.client_ca {
+            Some(ref path) => {
+                let mut roots = RootCertStore::empty();
+
+                for cert in read_certificates(path.as_str())? {
+                    if let Err(e) = roots.add(&cert) {
+                        return Err(SoapError::Tls(format!("{}: {:?}", path, e)));
+                    }
+                }
+
+                builder.with_client_cert_verifier(
+                    AllowAnyAuthenticatedClient::new(roots))
+            },
+            None => builder.with_no_client_auth(),
+        };
+
+        match builder.with_single_cert(certs, key) {
+            Ok(config) => Ok(TlsAcceptor::from(Arc::new(config))),
+            Err(e)     => Err(SoapError::Tls(e.to_string())),
+        }
+    }
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, SoapError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(rustls_pemfile::read_all(&mut reader)?)
+}
+
+fn read_certificates(path: &str) -> Result<Vec<Certificate>, SoapError> {
+    let certs: Vec<Certificate> = read_pem(path)?.into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect();
+
+    if certs.is_empty() {
+        return Err(SoapError::Tls(format!("No certificates found in {}", path)));
+    }
+
+    Ok(certs)
+}
+
+fn read_private_key(path: &str) -> Result<PrivateKey, SoapError> {
+    for item in read_pem(path)? {
+        match item {
+            Item::RSAKey(der) | Item::PKCS8Key(der) | Item::ECKey(der) =>
+                return Ok(PrivateKey(der)),
+            _ => (),
+        }
+    }
+
+    Err(SoapError::Tls(format!("No private key found in {}", path)))
+}
@@ -0,0 +1,618 @@
+//! WS-Addressing 1.0 (SOAP binding) support.
+
+use std::time::Duration;
+
+use futures::future::{ self, BoxFuture, FutureExt };
+use hyper::{ Body, Client as HttpClient, Method, Request as HttpRequest, Uri };
+use hyper::client::HttpConnector;
+use percent_encoding::percent_decode_str;
+use rand::RngCore;
+use sxd_document::dom::{ Document, Element };
+
+use crate::error::SoapError;
+use crate::soap::{ Fault, Middleware, Next, Options, Request, Response };
+use crate::soap::security::text;
+
+pub const WSA_NS:  &str = "http://www.w3.org/2005/08/addressing";
+pub const WSAM_NS: &str = "http://www.w3.org/2007/05/addressing/metadata";
+
+pub const ANONYMOUS:    &str = "http://www.w3.org/2005/08/addressing/anonymous";
+pub const NONE:         &str = "http://www.w3.org/2005/08/addressing/none";
+pub const FAULT_ACTION: &str = "http://www.w3.org/2005/08/addressing/fault";
+
+/// Where a message should be sent. Reference parameters and metadata are
+/// not kept.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointReference {
+    pub address: String,
+}
+
+impl EndpointReference {
+    pub fn new(address: &str) -> EndpointReference {
+        EndpointReference {
+            address: address.to_string(),
+        }
+    }
+
+    /// Replies go back on the same HTTP connection.
+    pub fn is_anonymous(&self) -> bool {
+        self.address == ANONYMOUS
+    }
+
+    /// Replies are to be dropped.
+    pub fn is_none(&self) -> bool {
+        self.address == NONE
+    }
+}
+
+/// The addressing headers of a received message.
+#[derive(Clone, Debug, Default)]
+pub struct Headers {
+    pub to:         Option<String>,
+    pub action:     Option<String>,
+    pub message_id: Option<String>,
+    pub relates_to: Option<String>,
+    pub reply_to:   Option<EndpointReference>,
+    pub fault_to:   Option<EndpointReference>,
+
+    /// Qualified name of the first header that appeared more than once or
+    /// could not be read.
+    pub invalid: Option<String>,
+}
+
+impl Headers {
+    /// Reads the WS-Addressing blocks of a `SOAP-ENV:Header`. `None` when
+    /// there are none.
+    pub fn from_xml(header: Element) -> Option<Headers> {
+        let mut headers = Headers::default();
+        let mut found   = false;
+
+        for elem in header.children().into_iter().filter_map(|c| c.element()) {
+            if elem.name().namespace_uri() != Some(WSA_NS) {
+                continue;
+            }
+
+            found = true;
+
+            let name = elem.name().local_part();
+
+            let duplicate = match name {
+                "To" | "Action" | "MessageID" | "RelatesTo" => {
+                    let slot = match name {
+                        "To"        => &mut headers.to,
+                        "Action"    => &mut headers.action,
+                        "MessageID" => &mut headers.message_id,
+                        _           => &mut headers.relates_to,
+                    };
+
+                    let duplicate = slot.is_some();
+                    *slot = Some(text(elem));
+                    duplicate
+                },
+                "ReplyTo" | "FaultTo" => {
+                    let address = address(elem);
+
+                    if address.is_none() && headers.invalid.is_none() {
+                        headers.invalid = Some(format!("wsa:{}", name));
+                    }
+
+                    let slot = match name {
+                        "ReplyTo" => &mut headers.reply_to,
+                        _         => &mut headers.fault_to,
+                    };
+
+                    let duplicate = slot.is_some();
+                    *slot = address.map(|a| EndpointReference::new(a.as_str()));
+                    duplicate
+                },
+                _ => continue,
+            };
+
+            if duplicate && headers.invalid.is_none() {
+                headers.invalid = Some(format!("wsa:{}", name));
+            }
+        }
+
+        if found {
+            Some(headers)
+        } else {
+            None
+        }
+    }
+
+    /// Where the reply goes; anonymous when `ReplyTo` was left out.
+    pub fn reply_to(&self) -> EndpointReference {
+        self.reply_to.clone().unwrap_or_else(|| EndpointReference::new(ANONYMOUS))
+    }
+
+    /// Where faults go; `FaultTo`, or else the same place as replies.
+    pub fn fault_to(&self) -> EndpointReference {
+        self.fault_to.clone().unwrap_or_else(|| self.reply_to())
+    }
+
+    /// Whether the reply has to be sent somewhere other than the HTTP
+    /// back-channel.
+    pub fn is_deferred(&self) -> bool {
+        !self.reply_to().is_anonymous()
+    }
+}
+
+/// Delivery of replies to non-anonymous `ReplyTo` and `FaultTo` addresses.
+///
+/// Given to `soap::Service::set_delivery`, it makes the service answer such
+/// requests with an empty `202 Accepted` right away, run the operation in
+/// the background and POST the response to `ReplyTo` (or the fault to
+/// `FaultTo`), retrying failed attempts with exponential backoff. Use it
+/// along with the `Addressing` middleware, which adds the headers that let
+/// the receiver match the reply to its request.
+///
+/// Replies only go to addresses under one given to `allow`, and only for
+/// authenticated callers; other requests naming somewhere else get a fault.
+/// Only `http` addresses can be reached, so no others are allowed.
+#[derive(Clone, Debug)]
+pub struct Delivery {
+    attempts: u32,
+    backoff:  Duration,
+    timeout:  Duration,
+    allowed:  Vec<Uri>,
+    http:     HttpClient<HttpConnector>,
+}
+
+impl Default for Delivery {
+    fn default() -> Delivery {
+        Delivery::new()
+    }
+}
+
+impl Delivery {
+    pub fn new() -> Delivery {
+        Delivery {
+            attempts: 5,
+            backoff:  Duration::from_secs(1),
+            timeout:  Duration::from_secs(30),
+            allowed:  vec![],
+            http:     HttpClient::new(),
+        }
+    }
+
+    /// How many times to try each reply before giving up.
+    pub fn attempts(mut self, attempts: u32) -> Delivery {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Wait before the first retry; it doubles after each one.
+    pub fn backoff(mut self, backoff: Duration) -> Delivery {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Limit for each attempt, including reading the receiver's answer.
+    pub fn timeout(mut self, timeout: Duration) -> Delivery {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Lets replies go to `address` and anything below it, e.g.
+    /// `http://partner.example.com/replies/`. Addresses that can't be
+    /// parsed, or that aren't `http`, are ignored.
+    pub fn allow(mut self, address: &str) -> Delivery {
+        if let Ok(uri) = address.parse::<Uri>() {
+            if uri.scheme_str() == Some("http") {
+                self.allowed.push(uri);
+            }
+        }
+
+        self
+    }
+
+    /// Whether replies may be sent to `address`: an `http` address with
+    /// the same host and port as an allowed address, and a path under its
+    /// path.
+    pub fn allows(&self, address: &str) -> bool {
+        let uri: Uri = match address.parse() {
+            Ok(uri) => uri,
+            Err(_)  => return false,
+        };
+
+        if uri.scheme_str() != Some("http") {
+            return false;
+        }
+
+        self.allowed.iter().any(|allowed| {
+            let base = allowed.path().trim_end_matches('/');
+
+            // Whole segments only: `/replies` doesn't allow `/replies-x`.
+            let below = match uri.path().strip_prefix(base) {
+                Some(rest) => rest.is_empty() || rest.starts_with('/'),
+                None       => false,
+            };
+
+            allowed.scheme() == uri.scheme() && allowed.authority() == uri.authority() && below
+        }) && !uri.path().split('/').any(|segment| {
+            // Nor does it allow `/replies/../admin`, however it is spelled.
+            let segment = percent_decode_str(segment).decode_utf8_lossy();
+            segment == "." || segment == ".."
+        })
+    }
+
+    /// POSTs `xml` to `address` until the receiver answers with a 2xx.
+    pub async fn send(&self, address: &str, xml: String) -> Result<(), SoapError> {
+        let mut wait  = self.backoff;
+        let mut error = SoapError::Timeout;
+
+        for attempt in 0..self.attempts {
+            if attempt > 0 {
+                tokio::time::sleep(wait).await;
+                wait *= 2;
+            }
+
+            let request = HttpRequest::builder()
+                .method(Method::POST)
+                .uri(address)
+                .header("Content-Type", "text/xml; charset=utf-8")
+                .header("SOAPAction", "\"\"")
+                .body(Body::from(xml.clone()));
+
+            let request = match request {
+                Ok(request) => request,
+                Err(e)      => return Err(SoapError::Malformed(e.to_string())),
+            };
+
+            match tokio::time::timeout(self.timeout, self.http.request(request)).await {
+                Ok(Ok(response)) => {
+                    if response.status().is_success() {
+                        return Ok(());
+                    }
+
+                    error = SoapError::Unexpected(format!(
+                        "{} answered with {}", address, response.status()));
+                },
+                Ok(Err(e)) => error = SoapError::Http(e),
+                Err(_)     => error = SoapError::Timeout,
+            }
+        }
+
+        Err(error)
+    }
+}
+
+/// The default action of an operation's message, as WS-Addressing Metadata
+/// derives it: target namespace, port type and message name. `suffix` is
+/// `Request` or `Response`.
+pub fn action(options: &Options, operation: &str, suffix: &str) -> String {
+    format_action(options.namespace.as_str(), options.service_name.as_str(), operation, suffix)
+}
+
+fn format_action(namespace: &str, service_name: &str, operation: &str, suffix: &str) -> String {
+    format!("urn:{}:{}PortType:{}{}", namespace, service_name, operation, suffix)
+}
+
+/// WS-Addressing processing, as SOAP middleware.
+///
+/// Requests carrying addressing headers must have a `wsa:Action` matching
+/// the operation and, unless the operation is one-way, a `wsa:MessageID`;
+/// otherwise they get the standard addressing faults. Non-anonymous
+/// `ReplyTo` and `FaultTo` addresses are only accepted when the service
+/// has a `Delivery` that allows them. Responses get `wsa:Action` and
+/// `wsa:RelatesTo`, plus `wsa:To` when they are delivered elsewhere.
+/// Requests without addressing headers pass untouched unless addressing is
+/// `required`.
+#[derive(Clone, Debug)]
+pub struct Addressing {
+    namespace:    String,
+    service_name: String,
+    required:     bool,
+}
+
+impl Addressing {
+    /// Addressing for the service configured with `options`, whose actions
+    /// it checks against.
+    pub fn new(options: &Options) -> Addressing {
+        Addressing {
+            namespace:    options.namespace.clone(),
+            service_name: options.service_name.clone(),
+            required:     false,
+        }
+    }
+
+    /// Rejects requests without addressing headers.
+    pub fn required(mut self) -> Addressing {
+        self.required = true;
+        self
+    }
+
+    fn action(&self, operation: &str, suffix: &str) -> String {
+        format_action(self.namespace.as_str(), self.service_name.as_str(), operation, suffix)
+    }
+
+    /// The fault for a request that breaks the rules, if it does.
+    fn check(&self, request: &Request) -> Option<Response> {
+        let headers = match request.context.addressing {
+            Some(ref headers) => headers,
+            None if self.required => {
+                return Some(fault("MessageAddressingHeaderRequired",
+                    "A required header representing a Message Addressing Property is not present",
+                    Some("wsa:Action"), None));
+            },
+            None => return None,
+        };
+
+        let relates_to = headers.message_id.clone();
+
+        if let Some(ref name) = headers.invalid {
+            return Some(fault("InvalidAddressingHeader",
+                "A header representing a Message Addressing Property is not valid and the message cannot be processed",
+                Some(name.as_str()), relates_to));
+        }
+
+        let action = match headers.action {
+            Some(ref action) => action,
+            None => {
+                return Some(fault("MessageAddressingHeaderRequired",
+                    "A required header representing a Message Addressing Property is not present",
+                    Some("wsa:Action"), relates_to));
+            },
+        };
+
+        // One-way messages get no reply to relate to theirs.
+        if headers.message_id.is_none() && request.context.expects_reply() {
+            return Some(fault("MessageAddressingHeaderRequired",
+                "A required header representing a Message Addressing Property is not present",
+                Some("wsa:MessageID"), None));
+        }
+
+        if *action != self.action(request.operation.as_str(), "Request") {
+            let mut response = fault("ActionNotSupported",
+                format!("The {} cannot be processed at the receiver", action).as_str(),
+                None, relates_to);
+
+            let action = action.clone();
+            response.add_header(move |document| {
+                header(document, "FaultDetail", Some(("Action", action.as_str())))
+            });
+
+            return Some(response);
+        }
+
+        let anonymous = |epr: &EndpointReference| epr.is_anonymous() || epr.is_none();
+
+        let delivery = match request.context.get::<Delivery>() {
+            Some(delivery) => delivery,
+            None => {
+                if !anonymous(&headers.reply_to()) || !anonymous(&headers.fault_to()) {
+                    let name = if anonymous(&headers.reply_to()) { "wsa:FaultTo" } else { "wsa:ReplyTo" };
+
+                    return Some(fault("OnlyAnonymousAddressSupported",
+                        "A header representing a Message Addressing Property is not valid and the message cannot be processed",
+                        Some(name), relates_to));
+                }
+
+                return None;
+            },
+        };
+
+        let reachable = |epr: &EndpointReference| anonymous(epr) || delivery.allows(epr.address.as_str());
+
+        if !reachable(&headers.reply_to()) || !reachable(&headers.fault_to()) {
+            let name = if reachable(&headers.reply_to()) { "wsa:FaultTo" } else { "wsa:ReplyTo" };
+
+            return Some(fault("InvalidAddressingHeader",
+                "A header representing a Message Addressing Property is not valid and the message cannot be processed",
+                Some(name), relates_to));
+        }
+
+        None
+    }
+}
+
+impl Middleware for Addressing {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>)
+        -> BoxFuture<'a, Response>
+    {
+        if let Some(response) = self.check(&request) {
+            return future::ready(response).boxed();
+        }
+
+        let headers   = request.context.addressing.clone();
+        let operation = request.operation.clone();
+
+        async move {
+            let mut response = next.run(request).await;
+
+            let headers = match headers {
+                Some(headers) => headers,
+                None          => return response,
+            };
+
+            if let Some(relates_to) = headers.message_id.clone() {
+                let (action, to) = match response.get_fault() {
+                    Some(_) => (String::from(FAULT_ACTION), headers.fault_to()),
+                    None    => (self.action(operation.as_str(), "Response"), headers.reply_to()),
+                };
+
+                if !to.is_anonymous() {
+                    response.add_header(move |document| {
+                        text_header(document, "To", to.address.as_str())
+                    });
+                }
+
+                reply_headers(&mut response, action, relates_to);
+            }
+
+            response
+        }.boxed()
+    }
+}
+
+/// Response carrying one of the faults defined by WS-Addressing. With the
+/// SOAP 1.1 binding, the offending header goes in a `wsa:FaultDetail`
+/// header block.
+fn fault(name: &str, string: &str, problem: Option<&str>, relates_to: Option<String>) -> Response {
+    let mut response = Response::new();
+    response.fault(Fault::qualified("wsa", WSA_NS, name, string));
+
+    if let Some(problem) = problem {
+        let problem = problem.to_string();
+
+        response.add_header(move |document| {
+            header(document, "FaultDetail", Some(("ProblemHeaderQName", problem.as_str())))
+        });
+    }
+
+    if let Some(relates_to) = relates_to {
+        reply_headers(&mut response, String::from(FAULT_ACTION), relates_to);
+    }
+
+    response
+}
+
+fn reply_headers(response: &mut Response, action: String, relates_to: String) {
+    let message_id = message_id();
+
+    response.add_header(move |document| text_header(document, "Action", action.as_str()));
+    response.add_header(move |document| text_header(document, "MessageID", message_id.as_str()));
+    response.add_header(move |document| text_header(document, "RelatesTo", relates_to.as_str()));
+}
+
+/// A `wsa:` header block, optionally wrapping one `wsa:` child with text.
+fn header<'d>(document: &Document<'d>, name: &str, child: Option<(&str, &str)>) -> Element<'d> {
+    let block = document.create_element(format!("wsa:{}", name).as_str());
+    block.set_attribute_value("xmlns:wsa", WSA_NS);
+
+    if let Some((name, value)) = child {
+        let child = document.create_element(format!("wsa:{}", name).as_str());
+        child.append_child(document.create_text(value));
+        block.append_child(child);
+    }
+
+    block
+}
+
+fn text_header<'d>(document: &Document<'d>, name: &str, value: &str) -> Element<'d> {
+    let block = header(document, name, None);
+    block.append_child(document.create_text(value));
+    block
+}
+
+/// A fresh `urn:uuid:` message id (random, version 4).
+pub fn message_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+
+    format!("urn:uuid:{}-{}-{}-{}-{}",
+        &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32])
+}
+
+fn address(element: Element) -> Option<String> {
+    element.children().into_iter()
+        .filter_map(|c| c.element())
+        .find(|e| e.name().namespace_uri() == Some(WSA_NS) && e.name().local_part() == "Address")
+        .map(text)
+        .filter(|address| address != "")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::soap::context::OneWay;
+
+    fn request(headers: Headers) -> Request {
+        let mut request = Request {
+            operation: String::from("echo"),
+            arguments: hashmap!{},
+            context:   Default::default(),
+        };
+
+        request.context.addressing = Some(headers);
+        request
+    }
+
+    fn fault_name(response: Option<Response>) -> Option<String> {
+        response.and_then(|response| response.get_fault().map(|fault| fault.code()))
+    }
+
+    #[test]
+    fn requires_a_message_id_when_a_reply_is_expected() {
+        let addressing = Addressing::new(&Options::default());
+        let headers    = Headers {
+            action: Some(addressing.action("echo", "Request")),
+            ..Headers::default()
+        };
+
+        assert_eq!(fault_name(addressing.check(&request(headers.clone()))),
+            Some(String::from("MessageAddressingHeaderRequired")));
+
+        let mut one_way = request(headers.clone());
+        one_way.context.insert(OneWay);
+        assert_eq!(fault_name(addressing.check(&one_way)), None);
+
+        let answered = Headers {
+            message_id: Some(message_id()),
+            ..headers
+        };
+
+        assert_eq!(fault_name(addressing.check(&request(answered))), None);
+    }
+
+    #[test]
+    fn checks_the_action() {
+        let addressing = Addressing::new(&Options::default());
+        let headers    = Headers {
+            action:     Some(String::from("urn:other")),
+            message_id: Some(message_id()),
+            ..Headers::default()
+        };
+
+        assert_eq!(fault_name(addressing.check(&request(headers))),
+            Some(String::from("ActionNotSupported")));
+    }
+
+    #[test]
+    fn delivers_only_below_allowed_addresses() {
+        let delivery = Delivery::new()
+            .allow("http://partner.example.com/replies")
+            .allow("http://other.example.com/")
+            // Can't be reached, so it isn't allowed.
+            .allow("https://secure.example.com/");
+
+        assert!(delivery.allows("http://partner.example.com/replies"));
+        assert!(delivery.allows("http://partner.example.com/replies/42"));
+        assert!(delivery.allows("http://other.example.com/anything"));
+        assert!(!delivery.allows("https://secure.example.com/anything"));
+
+        assert!(!delivery.allows("http://partner.example.com/replies-elsewhere"));
+        assert!(!delivery.allows("http://partner.example.com/replies/../admin"));
+        assert!(!delivery.allows("http://partner.example.com/replies/%2e%2E/admin"));
+        assert!(!delivery.allows("https://partner.example.com/replies"));
+        assert!(!delivery.allows("http://partner.example.com:8080/replies"));
+        assert!(!delivery.allows("http://partner.example.com.evil.com/replies"));
+        assert!(!delivery.allows("http://169.254.169.254/latest/meta-data"));
+        assert!(!Delivery::new().allows("http://partner.example.com/replies"));
+    }
+
+    #[test]
+    fn needs_a_delivery_for_other_addresses() {
+        let addressing = Addressing::new(&Options::default());
+        let headers    = Headers {
+            action:     Some(addressing.action("echo", "Request")),
+            message_id: Some(message_id()),
+            reply_to:   Some(EndpointReference::new("http://example.com/replies")),
+            ..Headers::default()
+        };
+
+        assert_eq!(fault_name(addressing.check(&request(headers.clone()))),
+            Some(String::from("OnlyAnonymousAddressSupported")));
+
+        let mut delivered = request(headers);
+        delivered.context.insert(Delivery::new().allow("http://example.com/replies"));
+        assert_eq!(fault_name(addressing.check(&delivered)), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::str;
+
+use hyper::{ Body, Client as HttpClient, Method, Request as HttpRequest };
+use hyper::client::HttpConnector;
+use sxd_document::Package;
+use sxd_document::writer::format_document;
+
+use crate::error::SoapError;
+use crate::soap::{ Part, Response };
+use crate::soap::encoding;
+use crate::soap::security::{ self, UsernameToken };
+
+/// Client for rpc/encoded services such as the ones `soap::Service` serves.
+pub struct Client {
+    uri:            String,
+    namespace:      String,
+    username_token: Option<UsernameToken>,
+    http:           HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// `namespace` is the one given in the service's `Options`.
+    pub fn new(uri: &str, namespace: &str) -> Client {
+        Client {
+            uri:            uri.to_string(),
+            namespace:      namespace.to_string(),
+            username_token: None,
+            http:           HttpClient::new(),
+        }
+    }
+
+    /// Adds a `wsse:Security` header with this token to every call.
+    pub fn set_username_token(&mut self, token: UsernameToken) {
+        self.username_token = Some(token);
+    }
+
+    /// The request envelope `call` would send.
+    pub fn envelope(&self, operation: &str, arguments: &HashMap<String, Part>)
+        -> String
+    {
+        let package  = Package::new();
+        let document = package.as_document();
+
+        let envelope = document.create_element("SOAP-ENV:Envelope");
+        envelope.set_attribute_value("xmlns:xsd", "http://www.w3.org/2001/XMLSchema");
+        envelope.set_attribute_value("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance");
+        envelope.set_attribute_value("xmlns:SOAP-ENV", "http://schemas.xmlsoap.org/soap/envelope/");
+        envelope.set_attribute_value("xmlns:SOAP-ENC", encoding::SOAP_ENC_NS);
+
+        if let Some(ref token) = self.username_token {
+            let header   = document.create_element("SOAP-ENV:Header");
+            let security = security::header(&document);
+
+            security.append_child(token.to_xml(&document));
+            header.append_child(security);
+            envelope.append_child(header);
+        }
+
+        let body = document.create_element("SOAP-ENV:Body");
+
+        let mut urn = String::from("urn:");
+        urn.push_str(self.namespace.as_str());
+
+        let mut op_name = String::from("ns1:");
+        op_name.push_str(operation);
+
+        let op = document.create_element(op_name.as_str());
+        op.set_attribute_value("xmlns:ns1", urn.as_str());
+
+        for (name, part) in arguments.iter() {
+            let arg = document.create_element(name.as_str());
+            arg.set_attribute_value("xsi:type", part.xsd_type().as_str());
+            encoding::write(&document, arg, part, &mut None);
+            op.append_child(arg);
+        }
+
+        body.append_child(op);
+        envelope.append_child(body);
+        document.root().append_child(envelope);
+
+        let mut buffer = vec![];
+        format_document(&document, &mut buffer)
+            .expect("Error while formatting SOAP XML");
+
+        String::from_utf8(buffer).unwrap()
+    }
+
+    /// Calls `operation` and waits for its response. Faults come back as a
+    /// `Response` whose `get_fault` is set, not as an error.
+    pub async fn call(&self, operation: &str, arguments: HashMap<String, Part>)
+        -> Result<Response, SoapError>
+    {
+        let response = self.http.request(self.request(operation, &arguments)?).await?;
+        let bytes    = hyper::body::to_bytes(response.into_body()).await?;
+
+        Response::from_xml(str::from_utf8(&bytes)?)
+    }
+
+    /// Calls a one-way `operation`. There is no response to wait for; this
+    /// only returns once the server has accepted the message.
+    pub async fn send(&self, operation: &str, arguments: HashMap<String, Part>)
+        -> Result<(), SoapError>
+    {
+        let response = self.http.request(self.request(operation, &arguments)?).await?;
+
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(SoapError::Unexpected(format!(
+                "Server answered a one-way call with {}", response.status())))
+        }
+    }
+
+    fn request(&self, operation: &str, arguments: &HashMap<String, Part>)
+        -> Result<HttpRequest<Body>, SoapError>
+    {
+        let request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(self.uri.as_str())
+            .header("Content-Type", "text/xml; charset=utf-8")
+            .header("SOAPAction", "\"\"")
+            .body(Body::from(self.envelope(operation, arguments)));
+
+        match request {
+            Ok(request) => Ok(request),
+            Err(e)      => Err(SoapError::Malformed(e.to_string())),
+        }
+    }
+}
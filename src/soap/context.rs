@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
+use hyper::http::Extensions;
+
+use crate::service::{ MimePart, Principal };
+use crate::soap::{ Part, State };
+use crate::soap::addressing;
+
+/// Marks the calls to one-way operations, set by the service before
+/// middleware runs.
+pub(crate) struct OneWay;
+
+/// Everything known about a SOAP call besides its arguments. Filled in from
+/// the HTTP request and the envelope before middleware runs; middleware may
+/// add to it through `extensions`.
+#[derive(Debug, Default)]
+pub struct Context {
+    pub request_id:  String,
+    pub remote_addr: Option<SocketAddr>,
+
+    /// HTTP headers, with lowercase names.
+    pub http_header: HashMap<String, String>,
+
+    /// Children of `SOAP-ENV:Header`, by local name.
+    pub soap_header: HashMap<String, Part>,
+
+    /// WS-Addressing headers, if the envelope had any.
+    pub addressing: Option<addressing::Headers>,
+
+    /// The envelope as it was received.
+    pub raw_xml: String,
+
+    /// MIME parts received along with the envelope.
+    pub attachments: Vec<MimePart>,
+
+    pub extensions: Extensions,
+}
+
+impl Context {
+    /// Typed value previously attached by middleware, if any.
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.extensions.get::<T>()
+    }
+
+    /// The service's shared state, if it was set to a `T`.
+    pub fn state<T: Send + Sync + 'static>(&self) -> Option<State<T>> {
+        self.extensions.get::<State<T>>().cloned()
+    }
+
+    /// Who the HTTP layer authenticated the caller as, if the route
+    /// requires authentication.
+    pub fn principal(&self) -> Option<&Principal> {
+        self.extensions.get::<Principal>()
+    }
+
+    /// Whether the caller gets a response: false for one-way operations.
+    pub fn expects_reply(&self) -> bool {
+        self.get::<OneWay>().is_none()
+    }
+
+    /// The attachment with this Content-ID, which may be given as a
+    /// `cid:` URL.
+    pub fn attachment(&self, id: &str) -> Option<&MimePart> {
+        let id = id.trim_start_matches("cid:");
+        self.attachments.iter().find(|attachment| attachment.content_id() == id)
+    }
+
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<T> {
+        self.extensions.insert(value)
+    }
+}
@@ -0,0 +1,366 @@
+//! SOAP 1.1 section 5 encoding details: multi-reference values.
+
+use std::cell::Cell;
+use std::collections::{ HashMap, HashSet };
+
+use sxd_document::dom::{ Document, Element };
+
+use crate::soap::Part;
+
+pub const SOAP_ENC_NS: &str = "http://schemas.xmlsoap.org/soap/encoding/";
+
+/// How many elements `MultiRefs` reads through references for one
+/// envelope. A value referenced from several places is read each time, so
+/// a few nested references can stand for exponentially many elements.
+pub const MAX_EXPANDED: usize = 10_000;
+
+/// Marks requests whose references were not all followed, as they read
+/// more than `MAX_EXPANDED` elements.
+pub(crate) struct TooManyRefs;
+
+/// Independent elements of a Body (`multiRef` and the like) by `id`, for
+/// resolving `href="#id"` accessors.
+pub struct MultiRefs<'d> {
+    ids:        HashMap<String, Element<'d>>,
+    referenced: HashSet<String>,
+    expanded:   Cell<usize>,
+}
+
+impl<'d> MultiRefs<'d> {
+    pub fn collect(body: Element<'d>) -> MultiRefs<'d> {
+        let mut ids        = hashmap!{};
+        let mut referenced = HashSet::new();
+        let mut pending    = vec![body];
+
+        while let Some(element) = pending.pop() {
+            if let Some(id) = element.attribute_value("id") {
+                ids.insert(id.to_string(), element);
+            }
+
+            if let Some(id) = href(element) {
+                referenced.insert(id);
+            }
+
+            pending.extend(element.children().into_iter().filter_map(|c| c.element()));
+        }
+
+        MultiRefs {
+            ids:        ids,
+            referenced: referenced,
+            expanded:   Cell::new(0),
+        }
+    }
+
+    /// Whether `element` is one of the independent elements, not the call.
+    pub fn is_independent(element: Element) -> bool {
+        element.attributes().iter().any(|attr| {
+            attr.name().local_part() == "root"
+                && attr.name().namespace_uri() == Some(SOAP_ENC_NS)
+                && attr.value().trim() == "0"
+        })
+    }
+
+    /// Reads `element` like `Part::from_xml`, following references. A
+    /// reference back to a value that contains it can't be represented in
+    /// a tree, so it is left as `Part::IdRef`, and so are all references
+    /// once `MAX_EXPANDED` elements were read through them. The `id` of an
+    /// element something refers to is left out; other ids are values.
+    pub fn resolve(&self, element: Element) -> Part {
+        self.read(element, &mut vec![])
+    }
+
+    /// Whether references were left unfollowed for reading too much.
+    pub fn exhausted(&self) -> bool {
+        self.expanded.get() > MAX_EXPANDED
+    }
+
+    fn read(&self, element: Element, path: &mut Vec<String>) -> Part {
+        if !path.is_empty() {
+            self.expanded.set(self.expanded.get() + 1);
+        }
+
+        let element = match href(element) {
+            Some(id) => {
+                if path.contains(&id) || self.exhausted() {
+                    return Part::IdRef(id);
+                }
+
+                match self.ids.get(&id) {
+                    Some(&target) => {
+                        path.push(id);
+                        let part = self.read(target, path);
+                        path.pop();
+
+                        return part;
+                    },
+                    None => return Part::IdRef(id),
+                }
+            },
+            None => element,
+        };
+
+        let part = Part::read(element, &mut |child| self.read(child, path));
+
+        match element.attribute_value("id") {
+            Some(id) if self.referenced.contains(id) => without_id(part),
+            _ => part,
+        }
+    }
+}
+
+/// `part` without the `id` attribute of its element, read as it would have
+/// been without it.
+fn without_id(part: Part) -> Part {
+    match part {
+        Part::ComplexType(mut attrs, mut content) => {
+            attrs.remove("id");
+
+            if attrs.is_empty() && content.len() == 1 && content.contains_key(Part::TEXT) {
+                content.remove(Part::TEXT).unwrap()
+            } else {
+                Part::ComplexType(attrs, content)
+            }
+        },
+        part => part,
+    }
+}
+
+/// Writes complex values once, as `multiRef` elements, and refers to them
+/// with `href`; equal values share one element.
+pub struct Shared<'d> {
+    seen:     Vec<(Part, String)>,
+    elements: Vec<Element<'d>>,
+}
+
+impl<'d> Shared<'d> {
+    pub fn new() -> Shared<'d> {
+        Shared {
+            seen:     vec![],
+            elements: vec![],
+        }
+    }
+
+    /// The `multiRef` elements written so far, to be appended to the Body
+    /// after the call.
+    pub fn into_elements(self) -> Vec<Element<'d>> {
+        self.elements
+    }
+}
+
+/// Writes `part` as the value of `element`.
+pub fn write<'d>(document: &Document<'d>, element: Element<'d>, part: &Part, shared: &mut Option<Shared<'d>>) {
+    match part {
+        &Part::ComplexType(ref attrs, ref content) => {
+            if let Some(ref mut refs) = *shared {
+                if let Some(&(_, ref id)) = refs.seen.iter().find(|&&(ref seen, _)| seen == part) {
+                    element.set_attribute_value("href", format!("#{}", id).as_str());
+                    return;
+                }
+            }
+
+            let target = match *shared {
+                Some(ref mut refs) => {
+                    let id = format!("id{}", refs.seen.len());
+                    refs.seen.push((part.clone(), id.clone()));
+
+                    element.set_attribute_value("href", format!("#{}", id).as_str());
+
+                    let multi_ref = document.create_element("multiRef");
+                    multi_ref.set_attribute_value("id", id.as_str());
+                    multi_ref.set_attribute_value("SOAP-ENC:root", "0");
+                    multi_ref
+                },
+                None => element,
+            };
+
+            for (name, value) in attrs.iter() {
+                target.set_attribute_value(name.as_str(), value.text().as_str());
+            }
+
+            for (name, value) in content.iter() {
+                if name == Part::TEXT {
+                    target.append_child(document.create_text(value.text().as_str()));
+                    continue;
+                }
+
+                let items = match value {
+                    &Part::Array(ref items) => items.iter().collect(),
+                    _                       => vec![value],
+                };
+
+                for item in items {
+                    let child = document.create_element(name.as_str());
+                    write(document, child, item, shared);
+                    target.append_child(child);
+                }
+            }
+
+            if target != element {
+                if let Some(ref mut refs) = *shared {
+                    refs.elements.push(target);
+                }
+            }
+        },
+        &Part::Array(ref items) => {
+            for item in items {
+                let child = document.create_element("item");
+                write(document, child, item, shared);
+                element.append_child(child);
+            }
+        },
+        &Part::Nil => {
+            element.set_attribute_value("xsi:nil", "true");
+        },
+        &Part::Optional(ref part) => write(document, element, part, shared),
+        _ => {
+            let text = part.text();
+
+            if text != "" {
+                element.append_child(document.create_text(text.as_str()));
+            }
+        },
+    }
+}
+
+fn href(element: Element) -> Option<String> {
+    element.attribute_value("href")
+        .filter(|href| href.starts_with('#'))
+        .map(|href| href[1..].to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sxd_document::{ parser, Package };
+    use sxd_document::writer::format_document;
+
+    fn body(package: &Package) -> Element<'_> {
+        package.as_document().root().children()[0].element().unwrap()
+    }
+
+    #[test]
+    fn follows_references() {
+        let package = parser::parse(concat!(
+            r#"<Body xmlns:enc="http://schemas.xmlsoap.org/soap/encoding/">"#,
+            r#"<multiRef id="id0" enc:root="0"><street>Main</street></multiRef>"#,
+            r##"<call><home href="#id0"/><work href="#id0"/><other href="#missing"/></call>"##,
+            r#"</Body>"#)).unwrap();
+
+        let body = body(&package);
+        let refs = MultiRefs::collect(body);
+        let call = body.children()[1].element().unwrap();
+
+        assert!(MultiRefs::is_independent(body.children()[0].element().unwrap()));
+        assert!(!MultiRefs::is_independent(call));
+
+        match refs.resolve(call) {
+            Part::ComplexType(_, content) => {
+                let address = Part::ComplexType(hashmap!{}, hashmap!{
+                    String::from("street") => Part::String(String::from("Main")),
+                });
+
+                assert_eq!(content["home"], address);
+                assert_eq!(content["work"], address);
+                assert_eq!(content["other"], Part::IdRef(String::from("missing")));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+
+        assert!(!refs.exhausted());
+    }
+
+    #[test]
+    fn keeps_ids_nothing_refers_to() {
+        let package = parser::parse(concat!(
+            r#"<Body><multiRef id="id0">Main</multiRef>"#,
+            r##"<call><street href="#id0"/><user id="42">bob</user></call></Body>"##)).unwrap();
+
+        let body = body(&package);
+        let call = body.children()[1].element().unwrap();
+
+        match MultiRefs::collect(body).resolve(call) {
+            Part::ComplexType(_, content) => {
+                assert_eq!(content["street"], Part::String(String::from("Main")));
+                assert_eq!(content["user"], Part::ComplexType(hashmap!{
+                    String::from("id") => Part::String(String::from("42")),
+                }, hashmap!{
+                    String::from(Part::TEXT) => Part::String(String::from("bob")),
+                }));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn leaves_cycles_as_references() {
+        let package = parser::parse(concat!(
+            r##"<Body><call><node href="#a"/></call>"##,
+            r##"<multiRef id="a"><next href="#a"/></multiRef></Body>"##)).unwrap();
+
+        let body = body(&package);
+        let call = body.children()[0].element().unwrap();
+
+        match MultiRefs::collect(body).resolve(call) {
+            Part::ComplexType(_, content) => assert_eq!(content["node"], Part::ComplexType(hashmap!{}, hashmap!{
+                String::from("next") => Part::IdRef(String::from("a")),
+            })),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn stops_expanding_shared_references() {
+        // Each level refers to the next one twice: 2^40 elements in all.
+        let mut xml = String::from(r##"<Body><call><root href="#l0"/></call>"##);
+
+        for level in 0..40 {
+            xml.push_str(format!(
+                r##"<multiRef id="l{0}"><a href="#l{1}"/><b href="#l{1}"/></multiRef>"##,
+                level, level + 1).as_str());
+        }
+
+        xml.push_str(r#"<multiRef id="l40">leaf</multiRef></Body>"#);
+
+        let package = parser::parse(xml.as_str()).unwrap();
+        let body    = body(&package);
+        let refs    = MultiRefs::collect(body);
+
+        refs.resolve(body.children()[0].element().unwrap());
+
+        assert!(refs.exhausted());
+    }
+
+    #[test]
+    fn writes_equal_values_once() {
+        let package  = Package::new();
+        let document = package.as_document();
+        let root     = document.create_element("call");
+
+        let address = Part::ComplexType(hashmap!{}, hashmap!{
+            String::from("street") => Part::String(String::from("Main")),
+        });
+
+        let mut shared = Some(Shared::new());
+
+        for name in ["home", "work"] {
+            let element = document.create_element(name);
+            write(&document, element, &address, &mut shared);
+            root.append_child(element);
+        }
+
+        document.root().append_child(root);
+
+        for multi_ref in shared.unwrap().into_elements() {
+            root.append_child(multi_ref);
+        }
+
+        let mut buffer = vec![];
+        format_document(&document, &mut buffer).unwrap();
+        let xml = String::from_utf8(buffer).unwrap();
+
+        assert!(xml.contains("<home href='#id0'/><work href='#id0'/>"));
+        assert_eq!(xml.matches("<multiRef").count(), 1);
+        assert!(xml.contains("<street>Main</street>"));
+    }
+}
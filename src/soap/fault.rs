@@ -1,15 +1,35 @@
-extern crate sxd_document;
-use self::sxd_document::dom::{ Document, Element };
+use sxd_document::dom::{ Document, Element };
 
+#[derive(Clone, Debug, PartialEq)]
 enum FaultCode {
     VersionMismatch,
     MustUnderstand,
     Client,
     Server,
+
+    // A code defined elsewhere, e.g. by WS-Security. Boxed, as it is rare
+    // and would otherwise make every `Fault` (and every `Result` carrying
+    // one) three strings larger.
+    Qualified(Box<QualifiedCode>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct QualifiedCode {
+    prefix:    String,
+    namespace: String,
+    name:      String,
 }
 
 impl FaultCode {
-    pub fn to_xml<'a>(&'a self, document: &Document<'a>) -> Element {
+    fn qualified(prefix: &str, namespace: &str, name: &str) -> FaultCode {
+        FaultCode::Qualified(Box::new(QualifiedCode {
+            prefix:    prefix.to_string(),
+            namespace: namespace.to_string(),
+            name:      name.to_string(),
+        }))
+    }
+
+    pub fn to_xml<'a>(&'a self, document: &Document<'a>) -> Element<'a> {
         let code = document.create_element("faultcode");
         code.set_attribute_value("xsi:type", "xsd:string");
 
@@ -25,12 +45,38 @@ impl FaultCode {
 
             &FaultCode::Client =>
                 document.create_text("SOAP-ENV:Client"),
+
+            &FaultCode::Qualified(ref qualified) => {
+                if qualified.namespace != "" {
+                    code.set_attribute_value(
+                        format!("xmlns:{}", qualified.prefix).as_str(),
+                        qualified.namespace.as_str());
+                }
+
+                document.create_text(
+                    format!("{}:{}", qualified.prefix, qualified.name).as_str())
+            },
         });
 
         code
     }
+
+    fn parse(code: &str) -> FaultCode {
+        let mut split = code.rsplitn(2, ':');
+        let name      = split.next().unwrap_or("");
+        let prefix    = split.next().unwrap_or("");
+
+        match name {
+            "VersionMismatch" => FaultCode::VersionMismatch,
+            "MustUnderstand"  => FaultCode::MustUnderstand,
+            "Client"          => FaultCode::Client,
+            "Server"          => FaultCode::Server,
+            _ => FaultCode::qualified(prefix, "", name),
+        }
+    }
 }
 
+#[derive(Clone, Debug)]
 pub struct Fault {
     code:   FaultCode,
     string: String,
@@ -74,7 +120,57 @@ impl Fault {
         Fault::new(FaultCode::Client, string, actor, detail)
     }
 
-    pub fn to_xml<'a>(&'a self, document: &Document<'a>) -> Element {
+    /// Fault with a code from another specification, such as
+    /// `wsse:FailedAuthentication`. The namespace is declared on the
+    /// `faultcode` element itself.
+    pub fn qualified(prefix: &str, namespace: &str, name: &str, string: &str)
+        -> Fault
+    {
+        let code = FaultCode::qualified(prefix, namespace, name);
+
+        Fault::new(code, string, "", "")
+    }
+
+    /// Local name of the fault code, e.g. `Client` or `FailedAuthentication`.
+    pub fn code(&self) -> String {
+        match self.code {
+            FaultCode::VersionMismatch => String::from("VersionMismatch"),
+            FaultCode::MustUnderstand  => String::from("MustUnderstand"),
+            FaultCode::Client          => String::from("Client"),
+            FaultCode::Server          => String::from("Server"),
+            FaultCode::Qualified(ref qualified) => qualified.name.clone(),
+        }
+    }
+
+    pub fn string(&self) -> &str {
+        self.string.as_str()
+    }
+
+    /// Reads a received `Fault` element back.
+    pub fn from_xml(element: Element) -> Fault {
+        let mut fault = Fault::server("", "", "");
+
+        for child in element.children() {
+            if let Some(elem) = child.element() {
+                let text: String = elem.children().iter()
+                    .filter_map(|c| c.text())
+                    .map(|t| t.text())
+                    .collect();
+
+                match elem.name().local_part() {
+                    "faultcode"   => fault.code   = FaultCode::parse(text.trim()),
+                    "faultstring" => fault.string = text,
+                    "faultactor"  => fault.actor  = text,
+                    "detail"      => fault.detail = text,
+                    _ => (),
+                }
+            }
+        }
+
+        fault
+    }
+
+    pub fn to_xml<'a>(&'a self, document: &Document<'a>) -> Element<'a> {
         let fault = document.create_element("SOAP-ENV:Fault");
 
         let code = self.code.to_xml(document);
@@ -0,0 +1,378 @@
+//! Duplicate message suppression.
+
+use std::collections::{ HashMap, VecDeque };
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, SystemTime };
+
+use futures::future::{ BoxFuture, FutureExt };
+use tokio::sync::watch;
+
+use crate::soap::{ Middleware, Next, Part, Request, Response };
+
+/// What is kept of a response to answer a duplicate with it. The time is
+/// wall-clock time, so a store shared between instances can keep it too.
+#[derive(Clone, Debug)]
+pub struct Recorded {
+    pub operation:   String,
+    pub responses:   HashMap<String, Part>,
+    pub recorded_at: SystemTime,
+}
+
+impl Recorded {
+    fn response(&self) -> Response {
+        let mut response = Response::new();
+        response.operation = self.operation.clone();
+        response.responses = self.responses.clone();
+        response
+    }
+}
+
+/// Where `Idempotency` keeps responses. Implement it to share them
+/// between instances, e.g. in a database.
+pub trait ResponseStore: Send + Sync + 'static {
+    fn get(&self, key: &str) -> Option<Recorded>;
+    fn put(&self, key: &str, response: Recorded);
+}
+
+/// In-memory store that forgets the least recently used responses once it
+/// holds `capacity` of them. Replaying a response counts as using it.
+pub struct MemoryStore {
+    capacity: usize,
+    entries:  Mutex<Entries>,
+}
+
+/// Responses by key, and the keys from least to most recently used. A key
+/// used again is queued again; the stale place it had is told apart by its
+/// generation and skipped.
+struct Entries {
+    map:        HashMap<String, (Recorded, u64)>,
+    queue:      VecDeque<(String, u64)>,
+    generation: u64,
+}
+
+impl Entries {
+    /// Moves `key` to the back of the queue, if it is stored.
+    fn touch(&mut self, key: &str, capacity: usize) {
+        let entry = match self.map.get_mut(key) {
+            Some(entry) => entry,
+            None        => return,
+        };
+
+        self.generation += 1;
+        entry.1 = self.generation;
+        self.queue.push_back((key.to_string(), self.generation));
+
+        // Keys used over and over leave stale places behind; drop them now
+        // and then so the queue stays within twice the capacity.
+        if self.queue.len() > 2 * capacity {
+            let map = &self.map;
+            self.queue.retain(|&(ref key, used)| map.get(key).map(|entry| entry.1) == Some(used));
+        }
+    }
+}
+
+impl MemoryStore {
+    pub fn new(capacity: usize) -> MemoryStore {
+        MemoryStore {
+            capacity: capacity.max(1),
+            entries:  Mutex::new(Entries {
+                map:        hashmap!{},
+                queue:      VecDeque::new(),
+                generation: 0,
+            }),
+        }
+    }
+}
+
+impl ResponseStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<Recorded> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.touch(key, self.capacity);
+        entries.map.get(key).map(|entry| entry.0.clone())
+    }
+
+    fn put(&self, key: &str, response: Recorded) {
+        let mut entries = self.entries.lock().unwrap();
+
+        entries.map.insert(key.to_string(), (response, 0));
+        entries.touch(key, self.capacity);
+
+        let Entries { ref mut map, ref mut queue, .. } = *entries;
+
+        while map.len() > self.capacity {
+            if let Some((oldest, used)) = queue.pop_front() {
+                if map.get(&oldest).map(|entry| entry.1) == Some(used) {
+                    map.remove(&oldest);
+                }
+            }
+        }
+    }
+}
+
+/// Answers repeated requests with the response to the first one instead
+/// of running the operation again, as SOAP middleware.
+///
+/// Requests are told apart by their `wsa:MessageID`, or by the text of
+/// another SOAP header chosen with `header`; requests without one always
+/// run. A duplicate arriving while the first is still running waits for
+/// it. Responses are replayed for `window` after they were produced; faults
+/// are not recorded, so a failed call can be retried.
+///
+/// Responses are only replayed to the caller they were made for, as told by
+/// `Context::principal`, so add this after any authentication. Only the
+/// operation's result is kept: middleware added before this one runs again
+/// for duplicates.
+pub struct Idempotency {
+    store:    Arc<dyn ResponseStore>,
+    window:   Duration,
+    header:   Option<String>,
+    inflight: Mutex<HashMap<String, watch::Receiver<Option<Recorded>>>>,
+}
+
+impl Default for Idempotency {
+    fn default() -> Idempotency {
+        Idempotency::new()
+    }
+}
+
+impl Idempotency {
+    pub fn new() -> Idempotency {
+        Idempotency {
+            store:    Arc::new(MemoryStore::new(1024)),
+            window:   Duration::from_secs(600),
+            header:   None,
+            inflight: Mutex::new(hashmap!{}),
+        }
+    }
+
+    pub fn store<S: ResponseStore>(mut self, store: S) -> Idempotency {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// How long a response is replayed for.
+    pub fn window(mut self, window: Duration) -> Idempotency {
+        self.window = window;
+        self
+    }
+
+    /// Identifies requests by the SOAP header with this local name instead
+    /// of `wsa:MessageID`.
+    pub fn header(mut self, name: &str) -> Idempotency {
+        self.header = Some(name.to_string());
+        self
+    }
+
+    fn key(&self, request: &Request) -> Option<String> {
+        let id = match self.header {
+            Some(ref name) => request.context.soap_header.get(name).map(|part| part.text()),
+            None => request.context.addressing.as_ref()
+                .and_then(|headers| headers.message_id.clone()),
+        };
+
+        let principal = request.context.principal()
+            .map(|principal| (principal.scheme.as_str(), principal.name.as_str()));
+
+        // The caller and the operation are part of the key, so an id can't
+        // fetch someone else's response, or that of another operation.
+        id.filter(|id| id != "")
+            .map(|id| format!("{:?}", (principal, request.operation.as_str(), id)))
+    }
+
+    fn recorded(&self, key: &str) -> Option<Recorded> {
+        // Recorded "in the future" by an instance whose clock is ahead.
+        self.store.get(key)
+            .filter(|recorded| match recorded.recorded_at.elapsed() {
+                Ok(age) => age < self.window,
+                Err(_)  => true,
+            })
+    }
+}
+
+impl Middleware for Idempotency {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>)
+        -> BoxFuture<'a, Response>
+    {
+        async move {
+            let key = match self.key(&request) {
+                Some(key) => key,
+                None      => return next.run(request).await,
+            };
+
+            if let Some(recorded) = self.recorded(key.as_str()) {
+                return recorded.response();
+            }
+
+            // Either wait for the copy already running, or become it.
+            let running = {
+                let mut inflight = self.inflight.lock().unwrap();
+
+                match inflight.get(&key) {
+                    Some(receiver) => Err(receiver.clone()),
+                    None => {
+                        let (sender, receiver) = watch::channel(None);
+                        inflight.insert(key.clone(), receiver);
+                        Ok(sender)
+                    },
+                }
+            };
+
+            let sender = match running {
+                Ok(sender) => sender,
+                Err(mut receiver) => {
+                    while receiver.changed().await.is_ok() {
+                        if let Some(ref recorded) = *receiver.borrow() {
+                            return recorded.response();
+                        }
+                    }
+
+                    // The first copy failed or went away; run this one.
+                    return next.run(request).await;
+                },
+            };
+
+            // Recorded before the key stops counting as running, so no
+            // duplicate slips in between.
+            let _running = Running { inflight: &self.inflight, key: key.as_str() };
+            let response = next.run(request).await;
+
+            if response.get_fault().is_none() {
+                let recorded = Recorded {
+                    operation:   response.operation.clone(),
+                    responses:   response.responses.clone(),
+                    recorded_at: SystemTime::now(),
+                };
+
+                self.store.put(key.as_str(), recorded.clone());
+                let _ = sender.send(Some(recorded));
+            }
+
+            response
+        }.boxed()
+    }
+}
+
+/// Marks a key as no longer running when the first copy finishes, or is
+/// dropped halfway.
+struct Running<'a> {
+    inflight: &'a Mutex<HashMap<String, watch::Receiver<Option<Recorded>>>>,
+    key:      &'a str,
+}
+
+impl<'a> Drop for Running<'a> {
+    fn drop(&mut self) {
+        self.inflight.lock().unwrap().remove(self.key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::atomic::{ AtomicUsize, Ordering };
+
+    use futures::executor::block_on;
+    use futures::future;
+
+    use crate::service::Principal;
+    use crate::soap::addressing::Headers;
+
+    fn recorded(operation: &str) -> Recorded {
+        Recorded {
+            operation:   operation.to_string(),
+            responses:   hashmap!{},
+            recorded_at: SystemTime::now(),
+        }
+    }
+
+    fn request(message_id: &str, caller: Option<&str>) -> Request {
+        let mut request = Request {
+            operation: String::from("transfer"),
+            arguments: hashmap!{},
+            context:   Default::default(),
+        };
+
+        request.context.addressing = Some(Headers {
+            message_id: Some(message_id.to_string()),
+            ..Headers::default()
+        });
+
+        if let Some(name) = caller {
+            request.context.insert(Principal {
+                name:   name.to_string(),
+                scheme: String::from("UsernameToken"),
+            });
+        }
+
+        request
+    }
+
+    #[test]
+    fn replays_responses_to_their_own_caller_only() {
+        let idempotency = Idempotency::new();
+        let calls       = Arc::new(AtomicUsize::new(0));
+
+        let dispatch = move |request: Request| {
+            let count = calls.fetch_add(1, Ordering::SeqCst) + 1;
+
+            let mut response = Response::new();
+            response.operation = request.operation;
+            response.responses.insert(String::from("count"), Part::Int(count as i32));
+            future::ready(response).boxed()
+        };
+
+        let middleware: Vec<Arc<dyn Middleware>> = vec![];
+        let call = |request: Request| {
+            block_on(idempotency.handle(request, Next::new(&middleware, &dispatch))).responses["count"].clone()
+        };
+
+        assert_eq!(call(request("urn:uuid:1", Some("alice"))), Part::Int(1));
+        assert_eq!(call(request("urn:uuid:1", Some("alice"))), Part::Int(1));
+        assert_eq!(call(request("urn:uuid:1", Some("mallory"))), Part::Int(2));
+        assert_eq!(call(request("urn:uuid:1", None)), Part::Int(3));
+        assert_eq!(call(request("urn:uuid:2", Some("alice"))), Part::Int(4));
+    }
+
+    #[test]
+    fn memory_store_keeps_responses_in_use() {
+        let store = MemoryStore::new(2);
+
+        store.put("a", recorded("a"));
+        store.put("b", recorded("b"));
+        assert!(store.get("a").is_some());
+        store.put("c", recorded("c"));
+
+        assert!(store.get("b").is_none());
+        assert!(store.get("a").is_some());
+        assert!(store.get("c").is_some());
+
+        for _ in 0..100 {
+            store.get("a");
+        }
+
+        let entries = store.entries.lock().unwrap();
+        assert!(entries.queue.len() <= 4);
+    }
+
+    #[test]
+    fn memory_store_forgets_the_oldest_responses() {
+        let store = MemoryStore::new(2);
+
+        store.put("a", recorded("a"));
+        store.put("b", recorded("b"));
+        store.put("a", recorded("a again"));
+        store.put("c", recorded("c"));
+
+        assert!(store.get("b").is_none());
+        assert_eq!(store.get("a").map(|r| r.operation), Some(String::from("a again")));
+        assert_eq!(store.get("c").map(|r| r.operation), Some(String::from("c")));
+
+        for _ in 0..100 {
+            store.put("a", recorded("a"));
+        }
+
+        let entries = store.entries.lock().unwrap();
+        assert_eq!(entries.map.len(), 2);
+        assert!(entries.queue.len() <= 4);
+    }
+}
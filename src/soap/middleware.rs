@@ -0,0 +1,132 @@
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+
+use crate::soap::{ Request, Response };
+
+/// Terminal step of the chain: finds the operation and runs it.
+pub type Dispatch = dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync;
+
+/// Hook between envelope parsing and operation dispatch.
+///
+/// Middleware runs in the order it was added. It sees the parsed request
+/// before the operation does and the response before it is serialized, and
+/// may answer on its own (e.g. with a fault) by not calling `next`. Data
+/// meant for the operation goes in `Request::context`.
+pub trait Middleware: Send + Sync + 'static {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>)
+        -> BoxFuture<'a, Response>;
+}
+
+/// The rest of the chain, ending in the operation.
+pub struct Next<'a> {
+    middlewares: &'a [Arc<dyn Middleware>],
+    dispatch:    &'a Dispatch,
+}
+
+impl<'a> Next<'a> {
+    pub fn new(middlewares: &'a [Arc<dyn Middleware>], dispatch: &'a Dispatch) -> Next<'a> {
+        Next {
+            middlewares: middlewares,
+            dispatch:    dispatch,
+        }
+    }
+
+    pub fn run(self, request: Request) -> BoxFuture<'a, Response> {
+        match self.middlewares.split_first() {
+            Some((first, rest)) => first.handle(request, Next::new(rest, self.dispatch)),
+            None                => (self.dispatch)(request),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Mutex;
+
+    use futures::executor::block_on;
+    use futures::future::FutureExt;
+
+    use crate::soap::{ Context, Fault, Part };
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    /// Logs its way in and out, and marks the response it passes on. Answers
+    /// with a fault on its own if `stop`.
+    struct Step {
+        name: &'static str,
+        log:  Log,
+        stop: bool,
+    }
+
+    impl Middleware for Step {
+        fn handle<'a>(&'a self, request: Request, next: Next<'a>)
+            -> BoxFuture<'a, Response>
+        {
+            async move {
+                self.log.lock().unwrap().push(format!("{} in", self.name));
+
+                let mut response = if self.stop {
+                    let mut response = Response::new();
+                    response.fault(Fault::client("Stopped", "", ""));
+                    response
+                } else {
+                    next.run(request).await
+                };
+
+                response.responses.insert(self.name.to_string(), Part::Boolean(true));
+                self.log.lock().unwrap().push(format!("{} out", self.name));
+
+                response
+            }.boxed()
+        }
+    }
+
+    fn run(steps: &[(&'static str, bool)], log: &Log) -> Response {
+        let middlewares: Vec<Arc<dyn Middleware>> = steps.iter()
+            .map(|&(name, stop)| Arc::new(Step {
+                name: name,
+                log:  log.clone(),
+                stop: stop,
+            }) as Arc<dyn Middleware>)
+            .collect();
+
+        let operation = log.clone();
+        let dispatch  = move |_: Request| {
+            operation.lock().unwrap().push(String::from("operation"));
+            futures::future::ready(Response::new()).boxed()
+        };
+
+        let request = Request {
+            operation: String::from("op"),
+            arguments: hashmap!{},
+            context:   Context::default(),
+        };
+
+        block_on(Next::new(&middlewares, &dispatch).run(request))
+    }
+
+    #[test]
+    fn runs_in_the_order_added() {
+        let log      = Log::default();
+        let response = run(&[("first", false), ("second", false)], &log);
+
+        assert_eq!(*log.lock().unwrap(), vec!["first in", "second in", "operation", "second out", "first out"]);
+        assert!(response.get_fault().is_none());
+        assert_eq!(response.responses["first"], Part::Boolean(true));
+        assert_eq!(response.responses["second"], Part::Boolean(true));
+    }
+
+    #[test]
+    fn can_answer_on_its_own() {
+        let log      = Log::default();
+        let response = run(&[("first", false), ("second", true), ("third", false)], &log);
+
+        assert_eq!(*log.lock().unwrap(), vec!["first in", "second in", "second out", "first out"]);
+        assert_eq!(response.get_fault().map(|fault| fault.code()), Some(String::from("Client")));
+        assert!(!response.responses.contains_key("third"));
+        assert_eq!(response.responses["first"], Part::Boolean(true));
+    }
+}
@@ -1,16 +1,31 @@
+pub mod addressing;
+mod client;
+mod context;
+mod encoding;
 mod fault;
+pub mod idempotency;
+mod middleware;
+mod mtom;
 mod operation;
 mod options;
 mod part;
 mod request;
 mod response;
+pub mod security;
 mod service;
+mod state;
+mod validation;
 mod wsdl;
 
+pub use self::client::Client;
+pub use self::context::Context;
 pub use self::fault::Fault;
+pub use self::middleware::{ Middleware, Next };
 pub use self::operation::Operation;
 pub use self::options::Options;
 pub use self::part::Part;
 pub use self::request::Request;
-pub use self::response::Response;
+pub use self::response::{ Packaging, Response };
 pub use self::service::Service;
+pub use self::state::State;
+pub use self::validation::Validation;
@@ -0,0 +1,44 @@
+//! MTOM/XOP: binary parts sent as MIME attachments instead of inline
+//! base64.
+
+use percent_encoding::percent_decode_str;
+use sxd_document::dom::{ Document, Element };
+
+use crate::service::MimePart;
+use crate::soap::Part;
+
+pub const XOP_NS:   &str = "http://www.w3.org/2004/08/xop/include";
+pub const WSP_NS:   &str = "http://schemas.xmlsoap.org/ws/2004/09/policy";
+pub const WSOMA_NS: &str = "http://schemas.xmlsoap.org/ws/2004/09/policy/optimizedmimeserialization";
+
+/// Type of the root part of an MTOM message carrying a SOAP 1.1 envelope.
+pub const ROOT_TYPE: &str = "application/xop+xml; charset=UTF-8; type=\"text/xml\"";
+
+/// The binary content an element stands for, when it is just an
+/// `xop:Include` pointing at one of `attachments`.
+pub fn resolve(element: Element, attachments: &[MimePart]) -> Option<Part> {
+    let include = element.children().into_iter()
+        .filter_map(|c| c.element())
+        .find(|e| e.name().namespace_uri() == Some(XOP_NS) && e.name().local_part() == "Include")?;
+
+    let href = include.attribute_value("href")?;
+
+    if !href.starts_with("cid:") {
+        return None;
+    }
+
+    // `cid:` URLs escape the Content-ID like any other URL.
+    let id = percent_decode_str(&href[4..]).decode_utf8().ok()?;
+
+    attachments.iter()
+        .find(|attachment| attachment.content_id() == id)
+        .map(|attachment| Part::Binary(attachment.content.clone()))
+}
+
+/// An `xop:Include` for the attachment with `content_id`.
+pub fn include<'d>(document: &Document<'d>, content_id: &str) -> Element<'d> {
+    let include = document.create_element("xop:Include");
+    include.set_attribute_value("xmlns:xop", XOP_NS);
+    include.set_attribute_value("href", format!("cid:{}", content_id).as_str());
+    include
+}
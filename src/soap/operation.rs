@@ -1,23 +1,103 @@
 use std::collections::HashMap;
+use std::future::Future;
 
-use soap::{ Fault, Part, Request, Response };
+use futures::future::{ self, BoxFuture, FutureExt };
+
+use crate::soap::{ Fault, Part, Request, Response, State };
+
+/// Operation body. It may be invoked concurrently from several connections,
+/// so it has to be `Fn` and hand back a future.
+pub type Closure = Box<dyn Fn(Request) -> BoxFuture<'static, Response> + Send + Sync>;
 
 pub struct Operation {
     pub doc:     String,
     pub name:    String,
     pub inputs:  HashMap<String, Part>,
     pub outputs: HashMap<String, Part>,
-    pub closure: Box<FnMut(Request) -> Response>
+    pub closure: Closure,
+        one_way: bool,
 }
 
 impl Operation {
+    /// An operation with no documentation, inputs or outputs yet.
+    pub fn new(name: &str, closure: Closure) -> Operation {
+        Operation {
+            doc:     String::new(),
+            name:    name.to_string(),
+            inputs:  hashmap!{},
+            outputs: hashmap!{},
+            closure: closure,
+            one_way: false,
+        }
+    }
+
+    /// Makes this a one-way operation, with no output message. Callers get
+    /// an empty `202 Accepted` once the closure is done, or the fault with
+    /// a `500` if it, validation or middleware failed the call.
+    pub fn one_way(mut self) -> Operation {
+        self.one_way = true;
+        self
+    }
+
+    pub fn is_one_way(&self) -> bool {
+        self.one_way
+    }
+
+    /// Wraps a plain function as an operation closure. It runs on the
+    /// server's worker threads, so it should not block for long.
+    pub fn closure<F>(fun: F) -> Closure
+        where F: Fn(Request) -> Response + Send + Sync + 'static
+    {
+        Box::new(move |request| future::ready(fun(request)).boxed())
+    }
+
+    /// Wraps an async function as an operation closure, for handlers that
+    /// need to wait on databases or other services.
+    pub fn async_closure<F, R>(fun: F) -> Closure
+        where F: Fn(Request) -> R + Send + Sync + 'static,
+              R: Future<Output = Response> + Send + 'static
+    {
+        Box::new(move |request| fun(request).boxed())
+    }
+
+    /// Wraps a plain function that takes the service's state, as set with
+    /// `Service::set_state`, by reference.
+    pub fn stateful<S, F>(fun: F) -> Closure
+        where S: Send + Sync + 'static,
+              F: Fn(&S, Request) -> Response + Send + Sync + 'static
+    {
+        Box::new(move |request| {
+            let response = match request.context.state::<S>() {
+                Some(state) => fun(&state, request),
+                None        => missing_state(),
+            };
+
+            future::ready(response).boxed()
+        })
+    }
+
+    /// Async counterpart of `stateful`. The state is handed over as a
+    /// `State` handle so the returned future can hold on to it.
+    pub fn async_stateful<S, F, R>(fun: F) -> Closure
+        where S: Send + Sync + 'static,
+              F: Fn(State<S>, Request) -> R + Send + Sync + 'static,
+              R: Future<Output = Response> + Send + 'static
+    {
+        Box::new(move |request| {
+            match request.context.state::<S>() {
+                Some(state) => fun(state, request).boxed(),
+                None        => future::ready(missing_state()).boxed(),
+            }
+        })
+    }
+
     pub fn not_found() -> Operation {
         Operation {
             doc:     String::from("Handler for unknown operations."),
             name:    String::from("not_found"),
             inputs:  hashmap!{},
             outputs: hashmap!{},
-            closure: Box::new(|request| {
+            closure: Operation::closure(|request| {
                 let reason = format!(
                     "Operation \"{}\" is not defined in the WSDL for this service",
                     request.operation.as_str()
@@ -27,8 +107,14 @@ impl Operation {
                 response.fault(Fault::client(reason.as_str(), "", ""));
                 response
             }),
+            one_way: false,
         }
     }
 }
 
-unsafe impl Send for Operation {}
+fn missing_state() -> Response {
+    let mut response = Response::new();
+    response.fault(Fault::server(
+        "Operation expects service state that was not set", "", ""));
+    response
+}
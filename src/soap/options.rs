@@ -1,17 +1,46 @@
+use crate::service::{ TlsConfig, DEFAULT_MAX_BODY_SIZE };
+use crate::soap::Validation;
+
 pub struct Options {
     pub bind_addr:    String,
     pub namespace:    String,
     pub service_name: String,
+    pub tls:          Option<TlsConfig>,
+
+    /// Sends binary outputs as MTOM attachments, and says so in the
+    /// WSDL. MTOM requests are accepted either way.
+    pub mtom: bool,
+
+    /// Sends binary outputs as SOAP with Attachments MIME parts,
+    /// and binds binary parts with `mime:multipartRelated` in the WSDL.
+    /// `mtom` wins if both are set.
+    pub swa: bool,
+
+    /// Writes complex outputs as SOAP-ENC multi-reference values. See
+    /// `Response::set_multi_refs`.
+    pub multi_refs: bool,
+
+    /// Whether requests may carry parts their operation does not declare.
+    /// Missing parts and values of the wrong type are always a Client fault.
+    pub validation: Validation,
+
+    /// Largest request body read, attachments included, in bytes. Larger
+    /// ones are answered with a 413.
+    pub max_body_size: usize,
 }
 
 impl Default for Options {
     fn default() -> Options {
         Options {
-            bind_addr:    String::from(""),
-            namespace:    String::from("server"),
-            service_name: String::from("Service"),
+            bind_addr:     String::from(""),
+            namespace:     String::from("server"),
+            service_name:  String::from("Service"),
+            tls:           None,
+            mtom:          false,
+            swa:           false,
+            multi_refs:    false,
+            validation:    Validation::Lax,
+            max_body_size: DEFAULT_MAX_BODY_SIZE,
         }
     }
 }
-
-
@@ -1,6 +1,16 @@
 use std::collections::HashMap; 
 
-#[derive(Debug)]
+use bytes::Bytes;
+use sxd_document::dom::{ Attribute, ChildOfElement, Element };
+
+use crate::soap::encoding::SOAP_ENC_NS;
+
+pub const XSI_NS: &str = "http://www.w3.org/2001/XMLSchema-instance";
+
+/// A value of a request or response. More kinds of values may be added, so
+/// matches outside this crate need a wildcard arm.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
 pub enum Part {
     // String-derived
     Id(String),
@@ -40,16 +50,338 @@ pub enum Part {
     HexBinary(String),
     AnyUri(String),
 
+    /// Binary content kept as bytes, such as a received attachment. It is
+    /// sent like `Base64Binary`, and its text is the base64 form.
+    Binary(Bytes),
+
     // Attrs, Content.
     ComplexType(HashMap<String, Part>, HashMap<String, Part>),
+
+    /// Repeated elements, such as the items of a SOAP-ENC array. In a
+    /// complex type they are keyed by their shared name; on their own they
+    /// are written as `item` elements.
+    Array(Vec<Part>),
+
+    /// Sent as `xsi:nil="true"`: there, but without a value. An absent part
+    /// is simply not in the map, and an empty one is an empty `String`.
+    Nil,
+
+    /// Declares, in an `Operation`'s inputs or outputs, a part that may be
+    /// left out or sent as nil. Elsewhere it stands for the inner value.
+    Optional(Box<Part>),
 }
 
 impl Part {
+    /// Key of the text of a complex type that has attributes but no child
+    /// elements, as in `<price currency="EUR">10</price>`.
+    pub const TEXT: &'static str = "#text";
+
+    pub fn optional(part: Part) -> Part {
+        Part::Optional(Box::new(part))
+    }
+
+    pub fn is_nil(&self) -> bool {
+        match self {
+            &Part::Nil => true,
+            _ => false,
+        }
+    }
+
+    pub fn is_optional(&self) -> bool {
+        match self {
+            &Part::Optional(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this is, or declares, binary content.
+    pub fn is_binary(&self) -> bool {
+        match self {
+            &Part::Base64Binary(_) | &Part::Binary(_) => true,
+            &Part::Optional(ref part) => part.is_binary(),
+            _ => false,
+        }
+    }
+
+    /// The XSD type the value is checked against, as declared in the WSDL
+    /// and sent in `xsi:type`.
     pub fn xsd_type(&self) -> String {
         match self {
-            &Part::String(_) => "xsd:string",
-            _ => "xsd:string",
+            &Part::Id(_)               => "xsd:ID",
+            &Part::IdRef(_)            => "xsd:IDREF",
+            &Part::Language(_)         => "xsd:language",
+            &Part::Name(_)             => "xsd:Name",
+            &Part::NmToken(_)          => "xsd:NMTOKEN",
+            &Part::NormalizedString(_) => "xsd:normalizedString",
+            &Part::String(_)           => "xsd:string",
+            &Part::Token(_)            => "xsd:token",
+
+            &Part::Date(_)     => "xsd:date",
+            &Part::Time(_)     => "xsd:time",
+            &Part::DateTime(_) => "xsd:dateTime",
+            &Part::Duration(_) => "xsd:duration",
+
+            &Part::Byte(_)          => "xsd:byte",
+            &Part::Short(_)         => "xsd:short",
+            &Part::Int(_)           => "xsd:int",
+            &Part::Long(_)          => "xsd:long",
+            &Part::UnsignedByte(_)  => "xsd:unsignedByte",
+            &Part::UnsignedShort(_) => "xsd:unsignedShort",
+            &Part::UnsignedInt(_)   => "xsd:unsignedInt",
+            &Part::UnsignedLong(_)  => "xsd:unsignedLong",
+            &Part::Decimal(_)       => "xsd:decimal",
+            &Part::Integer(_)       => "xsd:integer",
+
+            &Part::NegativeInteger(_)    => "xsd:negativeInteger",
+            &Part::PositiveInteger(_)    => "xsd:positiveInteger",
+            &Part::NonNegativeInteger(_) => "xsd:nonNegativeInteger",
+            &Part::NonPositiveInteger(_) => "xsd:nonPositiveInteger",
+
+            &Part::Boolean(_)      => "xsd:boolean",
+            &Part::Base64Binary(_) => "xsd:base64Binary",
+            &Part::Binary(_)       => "xsd:base64Binary",
+            &Part::HexBinary(_)    => "xsd:hexBinary",
+            &Part::AnyUri(_)       => "xsd:anyURI",
+
+            &Part::ComplexType(_, _) | &Part::Nil => "xsd:anyType",
+            &Part::Array(_)                       => "SOAP-ENC:Array",
+
+            &Part::Optional(ref part) => return part.xsd_type(),
         }.to_string()
     }
+
+    /// Lexical form of simple values. Complex types have none.
+    pub fn text(&self) -> String {
+        match self {
+            &Part::Id(ref s) | &Part::IdRef(ref s) | &Part::Language(ref s) |
+            &Part::Name(ref s) | &Part::NmToken(ref s) |
+            &Part::NormalizedString(ref s) | &Part::String(ref s) |
+            &Part::Token(ref s) | &Part::Date(ref s) | &Part::Time(ref s) |
+            &Part::DateTime(ref s) | &Part::Duration(ref s) |
+            &Part::Base64Binary(ref s) | &Part::HexBinary(ref s) |
+            &Part::AnyUri(ref s) => s.clone(),
+
+            &Part::Byte(n)          => n.to_string(),
+            &Part::Short(n)         => n.to_string(),
+            &Part::Int(n)           => n.to_string(),
+            &Part::Long(n)          => n.to_string(),
+            &Part::UnsignedByte(n)  => n.to_string(),
+            &Part::UnsignedShort(n) => n.to_string(),
+            &Part::UnsignedInt(n)   => n.to_string(),
+            &Part::UnsignedLong(n)  => n.to_string(),
+            &Part::Decimal(n)       => n.to_string(),
+            &Part::Integer(n)       => n.to_string(),
+
+            &Part::PositiveInteger(n)    => n.to_string(),
+            &Part::NonNegativeInteger(n) => n.to_string(),
+            &Part::NegativeInteger(n)    => format!("-{}", n),
+            &Part::NonPositiveInteger(n) => if n == 0 { n.to_string() } else { format!("-{}", n) },
+
+            &Part::Boolean(b) => b.to_string(),
+
+            &Part::Binary(ref bytes) => base64::encode(bytes),
+
+            &Part::ComplexType(_, _) | &Part::Array(_) | &Part::Nil => String::new(),
+
+            &Part::Optional(ref part) => part.text(),
+        }
+    }
+
+    /// Reads a received element. Elements without attributes or child
+    /// elements become their text; anything else becomes a complex type
+    /// keyed by local name, where repeated children make up an `Array`.
+    pub fn from_xml(element: Element) -> Part {
+        Part::read(element, &mut Part::from_xml)
+    }
+
+    /// `from_xml`, with `child` reading the child elements. Attributes
+    /// that only describe the encoding (`xsi:type`, `SOAP-ENC:root`, ...)
+    /// are left out.
+    pub(crate) fn read<F>(element: Element, child: &mut F) -> Part
+        where F: FnMut(Element) -> Part
+    {
+        if is_nil_element(element) {
+            return Part::Nil;
+        }
+
+        let mut attrs   = hashmap!{};
+        let mut content = hashmap!{};
+        let mut text    = String::new();
+
+        let is_array = element.attributes().iter()
+            .any(|attr| attr.name().local_part() == "arrayType");
+
+        for attr in element.attributes() {
+            if is_bookkeeping(attr) {
+                continue;
+            }
+
+            attrs.insert(
+                attr.name().local_part().to_string(),
+                Part::String(attr.value().to_string())
+            );
+        }
+
+        for node in element.children() {
+            match node {
+                ChildOfElement::Element(elem) => {
+                    let name  = elem.name().local_part().to_string();
+                    let value = child(elem);
+
+                    collect(&mut content, name, value, is_array);
+                },
+                ChildOfElement::Text(t) => text.push_str(t.text()),
+                _ => (),
+            }
+        }
+
+        if content.is_empty() && attrs.is_empty() {
+            Part::String(text)
+        } else {
+            if content.is_empty() && !is_array {
+                content.insert(Part::TEXT.to_string(), Part::String(text));
+            }
+
+            Part::ComplexType(attrs, content)
+        }
+    }
+}
+
+/// Adds `value` to `content` under `name`. Repeated names make up an
+/// `Array`, as do all names in an encoded array (`is_array`).
+pub(crate) fn collect(content: &mut HashMap<String, Part>, name: String, value: Part, is_array: bool) {
+    match content.remove(&name) {
+        Some(Part::Array(mut items)) => {
+            items.push(value);
+            content.insert(name, Part::Array(items));
+        },
+        Some(first) => {
+            content.insert(name, Part::Array(vec![first, value]));
+        },
+        None if is_array => {
+            content.insert(name, Part::Array(vec![value]));
+        },
+        None => {
+            content.insert(name, value);
+        },
+    }
 }
 
+/// Attributes about the encoding rather than the value: `xsi:type` and
+/// friends, SOAP-ENC's `root` and `encodingStyle`. The `id` of multiRef
+/// targets is left to `MultiRefs`, as other elements may have ids of their
+/// own.
+fn is_bookkeeping(attr: Attribute) -> bool {
+    let name = attr.name();
+
+    match name.namespace_uri() {
+        Some(XSI_NS)      => true,
+        Some(SOAP_ENC_NS) => name.local_part() == "root",
+        Some(_)           => name.local_part() == "encodingStyle",
+        None              => false,
+    }
+}
+
+/// Whether `element` is marked `xsi:nil="true"`.
+pub fn is_nil_element(element: Element) -> bool {
+    element.attributes().iter().any(|attr| {
+        attr.name().local_part() == "nil"
+            && attr.name().namespace_uri() == Some(XSI_NS)
+            && (attr.value().trim() == "true" || attr.value().trim() == "1")
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sxd_document::parser;
+
+    fn read(xml: &str) -> Part {
+        let package = parser::parse(xml).unwrap();
+        let root    = package.as_document().root().children()[0].element().unwrap();
+
+        Part::from_xml(root)
+    }
+
+    #[test]
+    fn leaves_are_their_text() {
+        let xml = r#"<a xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance" xsi:type="xsd:string"> x </a>"#;
+        assert_eq!(read(xml), Part::String(String::from(" x ")));
+    }
+
+    #[test]
+    fn leaves_keep_their_attributes() {
+        match read(r#"<price currency="EUR">10</price>"#) {
+            Part::ComplexType(attrs, content) => {
+                assert_eq!(attrs["currency"], Part::String(String::from("EUR")));
+                assert_eq!(content[Part::TEXT], Part::String(String::from("10")));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn plain_ids_are_kept() {
+        match read(r#"<user id="42">bob</user>"#) {
+            Part::ComplexType(attrs, content) => {
+                assert_eq!(attrs["id"], Part::String(String::from("42")));
+                assert_eq!(content[Part::TEXT], Part::String(String::from("bob")));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repeated_children_become_an_array() {
+        match read("<list><item>a</item><item>b</item><other>c</other></list>") {
+            Part::ComplexType(_, content) => {
+                assert_eq!(content["item"], Part::Array(vec![
+                    Part::String(String::from("a")),
+                    Part::String(String::from("b")),
+                ]));
+                assert_eq!(content["other"], Part::String(String::from("c")));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn nil_is_neither_absent_nor_empty() {
+        let xml = r#"<a xmlns:xsi="http://www.w3.org/2001/XMLSchema-instance"><b xsi:nil="true"/><c xsi:nil="1">x</c><d/><e xsi:nil="false"/></a>"#;
+
+        match read(xml) {
+            Part::ComplexType(_, content) => {
+                assert_eq!(content["b"], Part::Nil);
+                assert_eq!(content["c"], Part::Nil);
+                assert_eq!(content["d"], Part::String(String::new()));
+                assert_eq!(content["e"], Part::String(String::new()));
+                assert!(!content.contains_key("f"));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn optional_parts_stand_for_their_value() {
+        let part = Part::optional(Part::Int(0));
+
+        assert!(part.is_optional());
+        assert!(!Part::Nil.is_optional());
+        assert_eq!(part.xsd_type(), Part::Int(0).xsd_type());
+        assert_eq!(Part::optional(Part::Int(3)).text(), "3");
+        assert_eq!(Part::Nil.text(), "");
+    }
+
+    #[test]
+    fn encoded_arrays_of_one_are_arrays() {
+        let xml = r#"<list xmlns:enc="http://schemas.xmlsoap.org/soap/encoding/" enc:arrayType="xsd:string[1]"><item>a</item></list>"#;
+
+        match read(xml) {
+            Part::ComplexType(_, content) => {
+                assert_eq!(content["item"], Part::Array(vec![Part::String(String::from("a"))]));
+            },
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
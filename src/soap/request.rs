@@ -1,11 +1,16 @@
 use std::collections::HashMap;
+use std::convert::TryFrom;
 
-use service::Request as ServiceRequest;
+use crate::error::SoapError;
+use crate::service::Request as ServiceRequest;
 
-extern crate sxd_document;
-use self::sxd_document::parser;
+use sxd_document::parser;
 
-use soap::Part;
+use crate::soap::{ Context, Part };
+use crate::soap::addressing;
+use crate::soap::encoding::{ MultiRefs, TooManyRefs };
+use crate::soap::mtom;
+use crate::soap::part;
 
 macro_rules! next_tag(
     ($node:ident, $tag:expr) => {{
@@ -26,60 +31,217 @@ macro_rules! next_tag(
 
 #[derive(Debug)]
 pub struct Request {
-    pub operation: String,
-    pub arguments: HashMap<String, Part>,
+    pub operation:  String,
+    pub arguments:  HashMap<String, Part>,
+    pub context:    Context,
 }
 
-impl From<ServiceRequest> for Request {
-    fn from(request: ServiceRequest) -> Request {
-        let package = parser::parse(request.content.as_str())
-            .expect("Failed to parse XML");
-        
+impl TryFrom<ServiceRequest> for Request {
+    type Error = SoapError;
+
+    /// Reads the envelope of a call. Fails if it is not XML, or has no
+    /// Body or no operation in it.
+    fn try_from(request: ServiceRequest) -> Result<Request, SoapError> {
+        let package = match parser::parse(request.content.as_str()) {
+            Ok(package) => package,
+            Err(_)      => return Err(SoapError::Malformed(String::from("Not well-formed XML"))),
+        };
+
         let document = package.as_document();
         let root     = document.root();
-        let envelope = next_tag!(root, "Envelope").unwrap();
-        let body     = next_tag!(envelope, "Body").unwrap();
+
+        let envelope = match next_tag!(root, "Envelope") {
+            Some(envelope) => envelope,
+            None           => return Err(SoapError::Malformed(String::from("Missing SOAP Envelope"))),
+        };
+
+        let body = match next_tag!(envelope, "Body") {
+            Some(body) => body,
+            None       => return Err(SoapError::Malformed(String::from("Missing SOAP Body"))),
+        };
         
         let mut req = Request {
-            operation: String::new(),
-            arguments: hashmap!{},
+            operation:  String::new(),
+            arguments:  hashmap!{},
+            context:    Context::default(),
         };
 
+        if let Some(header) = next_tag!(envelope, "Header") {
+            for child in header.children() {
+                if let Some(elem) = child.element() {
+                    req.context.soap_header.insert(
+                        elem.name().local_part().to_string(),
+                        Part::from_xml(elem)
+                    );
+                }
+            }
+
+            req.context.addressing = addressing::Headers::from_xml(header);
+        }
+
         let operation = {
             let mut e = None;
 
             for child in body.children() {
                 if let Some(elem) = child.element() {
+                    // Multi-reference values may come before the call.
+                    if MultiRefs::is_independent(elem) {
+                        continue;
+                    }
+
                     e = Some(elem);
                     break;
                 }
             }
 
-            e
-        }.unwrap();
+            match e {
+                Some(e) => e,
+                None    => return Err(SoapError::Malformed(String::from("Missing operation in SOAP Body"))),
+            }
+        };
+
+        let refs = MultiRefs::collect(body);
 
         req.operation = operation.name().local_part().to_string();
         
         for arg in operation.children().iter() {
             if let Some(elem) = arg.element() {
-                req.arguments.insert(
-                    elem.name().local_part().to_string(),
-                    {
-                        let mut e = None;
-
-                        for child in elem.children() {
-                            if let Some(elem) = child.text() {
-                                e = Some(elem)
-                            }
-                        }
-
-                        Part::String(e.unwrap().text().to_string())
-                    }
-                );
+                let value = match mtom::resolve(elem, &request.attachments) {
+                    Some(part) => part,
+                    None => match elem.attribute_value("href") {
+                        // SwA references (swaRef) point at an attachment;
+                        // the operation looks it up in the context.
+                        Some(href) if href.starts_with("cid:") => Part::AnyUri(href.to_string()),
+                        _ => refs.resolve(elem),
+                    },
+                };
+
+                // Repeated arguments are collected like repeated children.
+                part::collect(&mut req.arguments, elem.name().local_part().to_string(), value, false);
             }
         }
 
-        req
+        if refs.exhausted() {
+            req.context.insert(TooManyRefs);
+        }
+
+        req.context.request_id  = request.request_id;
+        req.context.remote_addr = request.remote_addr;
+        req.context.http_header = request.header;
+        req.context.raw_xml     = request.content;
+        req.context.extensions  = request.extensions;
+        req.context.attachments = request.attachments;
+
+        Ok(req)
     }
 }
 
+impl Request {
+    /// Adds the binary `inputs` bound to MIME in the WSDL, which are not in
+    /// the body at all. Their Content-IDs start with the part name, as the
+    /// WS-I Attachments Profile has it. Other attachments are only in the
+    /// context.
+    pub(crate) fn bind_attachments(&mut self, inputs: &HashMap<String, Part>) {
+        for attachment in self.context.attachments.iter() {
+            let name = match attachment.content_id().find('=') {
+                Some(end) => &attachment.content_id()[..end],
+                None      => continue,
+            };
+
+            let declared = inputs.get(name).map(|part| part.is_binary()).unwrap_or(false);
+
+            if declared && !self.arguments.contains_key(name) {
+                self.arguments.insert(name.to_string(), Part::Binary(attachment.content.clone()));
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use bytes::Bytes;
+
+    use crate::service::MimePart;
+
+    const UPLOAD: &str = concat!(
+        r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">"#,
+        r#"<SOAP-ENV:Body><upload><name>a.png</name></upload></SOAP-ENV:Body></SOAP-ENV:Envelope>"#);
+
+    #[test]
+    fn mime_parts_only_bind_to_binary_inputs() {
+        let mut request = ServiceRequest::new(hashmap!{}, UPLOAD.to_string());
+        request.attachments = vec![
+            MimePart::new("file=1@test", "image/png", "PNG"),
+            MimePart::new("name=2@test", "text/plain", "b.png"),
+            MimePart::new("admin=3@test", "text/plain", "true"),
+        ];
+
+        let mut request = Request::try_from(request).unwrap();
+        request.bind_attachments(&hashmap!{
+            String::from("file") => Part::optional(Part::Base64Binary(String::new())),
+            String::from("name") => Part::String(String::new()),
+        });
+
+        assert_eq!(request.arguments.len(), 2);
+        assert_eq!(request.arguments["file"], Part::Binary(Bytes::from("PNG")));
+        assert_eq!(request.arguments["name"], Part::String(String::from("a.png")));
+        assert_eq!(request.context.attachments.len(), 3);
+    }
+
+    #[test]
+    fn malformed_envelopes_are_rejected() {
+        let envelopes = [
+            "",
+            "<not xml",
+            "<Envelope/>",
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/"><SOAP-ENV:Body/></SOAP-ENV:Envelope>"#,
+        ];
+
+        for content in envelopes.iter() {
+            let request = ServiceRequest::new(hashmap!{}, content.to_string());
+            assert!(Request::try_from(request).is_err(), "{}", content);
+        }
+    }
+
+    #[test]
+    fn repeated_arguments_become_an_array() {
+        let content = concat!(
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">"#,
+            r#"<SOAP-ENV:Body><tag><name>a</name><name>b</name><all>c</all></tag></SOAP-ENV:Body></SOAP-ENV:Envelope>"#);
+
+        let request = Request::try_from(ServiceRequest::new(hashmap!{}, content.to_string())).unwrap();
+
+        assert_eq!(request.arguments["name"], Part::Array(vec![
+            Part::String(String::from("a")),
+            Part::String(String::from("b")),
+        ]));
+        assert_eq!(request.arguments["all"], Part::String(String::from("c")));
+    }
+
+    #[test]
+    fn mtom_attachments_are_passed_through() {
+        let content = concat!(
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">"#,
+            r#"<SOAP-ENV:Body><upload><file>"#,
+            r#"<xop:Include xmlns:xop="http://www.w3.org/2004/08/xop/include" href="cid:file%401"/>"#,
+            r#"</file></upload></SOAP-ENV:Body></SOAP-ENV:Envelope>"#);
+
+        let file = Bytes::from(vec![0u8, 1, 2, 255]);
+
+        let mut request = ServiceRequest::new(hashmap!{}, content.to_string());
+        request.attachments = vec![MimePart::new("file@1", "application/octet-stream", file.clone())];
+
+        let request = Request::try_from(request).unwrap();
+
+        match request.arguments["file"] {
+            Part::Binary(ref bytes) => {
+                assert_eq!(*bytes, file);
+                assert_eq!(bytes.as_ptr(), file.as_ptr());
+            },
+            ref other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -1,15 +1,61 @@
 use std::collections::HashMap;
 
-extern crate sxd_document;
-use self::sxd_document::Package;
-use self::sxd_document::writer::format_document;
+use bytes::Bytes;
+use sxd_document::{ parser, Package };
+use sxd_document::dom::{ Document, Element };
+use sxd_document::writer::format_document;
 
-use soap::{ Fault, Part };
+use crate::error::SoapError;
+use crate::service::MimePart;
+use crate::soap::{ Fault, Part };
+use crate::soap::encoding::{ self, Shared };
+use crate::soap::mtom;
+use crate::soap::security;
+
+/// Builds one element of the response's `SOAP-ENV:Header`. Elements
+/// belong to a document, so blocks are kept as builders until the
+/// envelope is written.
+pub type HeaderBlock = Box<dyn for<'d> Fn(&Document<'d>) -> Element<'d> + Send + Sync>;
+
+/// Rewrites a finished envelope, as added with `Response::add_filter`.
+pub type Filter = Box<dyn Fn(String) -> Result<String, Fault> + Send + Sync>;
+
+/// How `Base64Binary` and `Binary` outputs travel. Signatures only cover
+/// the envelope, so signed responses keep them inline whatever this says.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Packaging {
+    /// As base64 text in the envelope.
+    Inline,
+
+    /// As MTOM attachments, referenced with `xop:Include`.
+    Mtom,
+
+    /// As SOAP with Attachments MIME parts, left out of the envelope. Their
+    /// Content-IDs start with the part name, as in `file=1@webservices`.
+    Attachments,
+}
 
 pub struct Response {
     pub operation: String,
     pub responses: HashMap<String, Part>,
+
+    /// Namespace of the response element, such as `urn:server`. Services
+    /// set it to their own; without one the element is unqualified.
+    pub namespace: String,
+
         fault:     Option<Fault>,
+        headers:   Vec<HeaderBlock>,
+        security:  Vec<HeaderBlock>,
+        body_attr: Vec<(String, String)>,
+        filters:   Vec<Filter>,
+        attached:  Vec<MimePart>,
+        multi_ref: bool,
+}
+
+impl Default for Response {
+    fn default() -> Response {
+        Response::new()
+    }
 }
 
 impl Response {
@@ -17,15 +63,142 @@ impl Response {
         Response {
             operation: String::new(),
             responses: hashmap!{},
+            namespace: String::new(),
             fault:     None,
+            headers:   vec![],
+            security:  vec![],
+            body_attr: vec![],
+            filters:   vec![],
+            attached:  vec![],
+            multi_ref: false,
         }
     }
 
+    /// Writes complex outputs as SOAP-ENC `multiRef` elements referenced
+    /// with `href`, the way Axis 1 does, so equal values are sent once.
+    pub fn set_multi_refs(&mut self, multi_refs: bool) {
+        self.multi_ref = multi_refs;
+    }
+
+    /// Sends a file along with the response, as a MIME part. Returns the
+    /// `cid:` URL to refer to it from an output.
+    ///
+    /// Attached files are not covered by signing or encryption; the
+    /// `Encryption` middleware refuses to send them.
+    pub fn attach<C: Into<Bytes>>(&mut self, content_type: &str, content: C) -> String {
+        let id = format!("attachment{}@webservices", self.attached.len());
+        self.attached.push(MimePart::new(id.as_str(), content_type, content));
+
+        format!("cid:{}", id)
+    }
+
+    pub fn attachments(&self) -> &[MimePart] {
+        &self.attached
+    }
+
+    /// Sets an attribute on `SOAP-ENV:Body`, e.g. a `wsu:Id` to sign it.
+    pub fn set_body_attribute(&mut self, name: &str, value: &str) {
+        self.body_attr.push((name.to_string(), value.to_string()));
+    }
+
+    /// Adds a step that rewrites the finished envelope, such as signing or
+    /// encrypting it. Filters run in the order they were added. If one
+    /// fails, its fault is sent instead of the response.
+    pub fn add_filter<F>(&mut self, filter: F)
+        where F: Fn(String) -> Result<String, Fault> + Send + Sync + 'static
+    {
+        self.filters.push(Box::new(filter));
+    }
+
+    /// Adds a block to `SOAP-ENV:Header`.
+    pub fn add_header<F>(&mut self, block: F)
+        where F: for<'d> Fn(&Document<'d>) -> Element<'d> + Send + Sync + 'static
+    {
+        self.headers.push(Box::new(block));
+    }
+
+    /// Adds a token to the `wsse:Security` header block, which is only
+    /// written when it has something in it.
+    pub fn add_security_header<F>(&mut self, block: F)
+        where F: for<'d> Fn(&Document<'d>) -> Element<'d> + Send + Sync + 'static
+    {
+        self.security.push(Box::new(block));
+    }
+
     pub fn fault(&mut self, fault: Fault) {
         self.fault = Some(fault);
     }
 
+    pub fn get_fault(&self) -> Option<&Fault> {
+        self.fault.as_ref()
+    }
+
+    /// Reads a response envelope received from a server.
+    pub fn from_xml(xml: &str) -> Result<Response, SoapError> {
+        let package = match parser::parse(xml) {
+            Ok(package) => package,
+            Err(e)      => return Err(SoapError::Malformed(format!("{:?}", e))),
+        };
+
+        let document = package.as_document();
+        let mut response = Response::new();
+
+        let body = document.root().children().into_iter()
+            .filter_map(|c| c.element())
+            .find(|e| e.name().local_part() == "Envelope")
+            .and_then(|envelope| {
+                envelope.children().into_iter()
+                    .filter_map(|c| c.element())
+                    .find(|e| e.name().local_part() == "Body")
+            });
+
+        let body = match body {
+            Some(body) => body,
+            None       => return Err(SoapError::Malformed(String::from("Missing SOAP Body"))),
+        };
+
+        for child in body.children() {
+            if let Some(elem) = child.element() {
+                let name = elem.name().local_part();
+
+                if name == "Fault" {
+                    response.fault = Some(Fault::from_xml(elem));
+                    continue;
+                }
+
+                response.operation = name.trim_end_matches("Response").to_string();
+
+                for part in elem.children() {
+                    if let Some(part) = part.element() {
+                        response.responses.insert(
+                            part.name().local_part().to_string(),
+                            Part::from_xml(part)
+                        );
+                    }
+                }
+            }
+        }
+
+        Ok(response)
+    }
+
+    /// The envelope, or the fault of the filter that failed on it.
     pub fn to_xml_string(&self) -> String {
+        match self.to_mime(Packaging::Inline) {
+            Ok((xml, _)) => xml,
+            Err(fault)   => self.faulted(fault).to_xml_string(),
+        }
+    }
+
+    /// The envelope, and the MIME parts to send along with it: the
+    /// attached files, plus binary outputs unless they are `Inline` or the
+    /// envelope goes through filters, which would not see them. Fails with
+    /// the fault of the first filter that fails.
+    pub fn to_mime(&self, packaging: Packaging) -> Result<(String, Vec<MimePart>), Fault> {
+        let mut attachments = vec![];
+
+        let packaging = if self.filters.is_empty() { packaging } else { Packaging::Inline };
+
         let package  = Package::new();
         let document = package.as_document();
 
@@ -33,28 +206,82 @@ impl Response {
         envelope.set_attribute_value("xmlns:xsd", "http://www.w3.org/2001/XMLSchema");
         envelope.set_attribute_value("xmlns:xsi", "http://www.w3.org/2001/XMLSchema-instance");
         envelope.set_attribute_value("xmlns:SOAP-ENV", "http://schemas.xmlsoap.org/soap/envelope/");
+        envelope.set_attribute_value("xmlns:SOAP-ENC", encoding::SOAP_ENC_NS);
+
+        if !self.headers.is_empty() || !self.security.is_empty() {
+            let header = document.create_element("SOAP-ENV:Header");
+
+            if !self.security.is_empty() {
+                let wsse = security::header(&document);
+
+                for block in self.security.iter() {
+                    wsse.append_child(block(&document));
+                }
+
+                header.append_child(wsse);
+            }
+
+            for block in self.headers.iter() {
+                header.append_child(block(&document));
+            }
+
+            envelope.append_child(header);
+        }
 
         let body = document.create_element("SOAP-ENV:Body");
 
-        let mut res_name = String::from("ns1:");
+        for &(ref name, ref value) in self.body_attr.iter() {
+            body.set_attribute_value(name.as_str(), value.as_str());
+        }
+
+        let mut res_name = if self.namespace.is_empty() {
+            String::new()
+        } else {
+            String::from("ns1:")
+        };
+
         res_name.push_str(self.operation.as_str());
         res_name.push_str("Response");
 
         let res = document.create_element(res_name.as_str());
 
-        for (_, part) in self.responses.iter() {
-            let ret = document.create_element("return");
+        if !self.namespace.is_empty() {
+            res.set_attribute_value("xmlns:ns1", self.namespace.as_str());
+        }
+
+        let mut shared = if self.multi_ref {
+            Some(Shared::new())
+        } else {
+            None
+        };
+
+        for (name, part) in self.responses.iter() {
+            // Accessors are named after their part, as in the WSDL.
+            let ret = document.create_element(name.as_str());
             ret.set_attribute_value("xsi:type", part.xsd_type().as_str());
 
-            let content = match part {
-                &Part::String(ref string) => string.clone(),
-                _ => String::new(),
+            // Binary values are attached as they are; base64 text is
+            // decoded first, and left inline if it isn't valid.
+            let bytes = match (packaging, part) {
+                (Packaging::Inline, _)             => None,
+                (_, &Part::Binary(ref bytes))      => Some(bytes.clone()),
+                (_, &Part::Base64Binary(ref data)) => base64::decode(data).ok().map(Bytes::from),
+                _                                  => None,
             };
 
-            if content != "" {
-                ret.append_child(document.create_text(content.as_str()));
+            if let Some(bytes) = bytes {
+                let id = format!("{}={}@webservices", name, attachments.len());
+                attachments.push(MimePart::new(id.as_str(), "application/octet-stream", bytes));
+
+                if packaging == Packaging::Mtom {
+                    ret.append_child(mtom::include(&document, id.as_str()));
+                    res.append_child(ret);
+                }
+
+                continue;
             }
-            
+
+            encoding::write(&document, ret, part, &mut shared);
             res.append_child(ret);
         }
 
@@ -64,14 +291,123 @@ impl Response {
         }
 
         body.append_child(res);
+
+        if let Some(shared) = shared {
+            for multi_ref in shared.into_elements() {
+                body.append_child(multi_ref);
+            }
+        }
+
         envelope.append_child(body);
         document.root().append_child(envelope);
 
         let mut buffer = vec![];
-        format_document(&document, &mut buffer).ok()
+        format_document(&document, &mut buffer)
             .expect("Error while formatting SOAP XML");
                     
-        String::from_utf8(buffer).unwrap()
+        let xml = String::from_utf8(buffer).unwrap();
+
+        let xml = self.filters.iter().try_fold(xml, |xml, filter| filter(xml))?;
+
+        attachments.extend(self.attached.iter().cloned());
+
+        Ok((xml, attachments))
+    }
+
+    /// A response to the same operation carrying only `fault`, to send
+    /// instead of this one.
+    pub(crate) fn faulted(&self, fault: Fault) -> Response {
+        let mut response = Response::new();
+        response.operation = self.operation.clone();
+        response.namespace = self.namespace.clone();
+        response.fault(fault);
+        response
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn download() -> Response {
+        let mut response = Response::new();
+        response.operation = String::from("download");
+        response.responses.insert(String::from("file"), Part::Binary(Bytes::from(vec![0u8, 255])));
+        response
+    }
+
+    #[test]
+    fn binary_outputs_are_attached_as_they_are() {
+        let response = download();
+
+        let (xml, attachments) = response.to_mime(Packaging::Mtom).unwrap();
+        assert_eq!(attachments.len(), 1);
+        assert_eq!(attachments[0].content, Bytes::from(vec![0u8, 255]));
+        assert!(xml.contains(format!("cid:{}", attachments[0].content_id()).as_str()));
+
+        let (xml, attachments) = response.to_mime(Packaging::Attachments).unwrap();
+        assert!(attachments[0].content_id().starts_with("file="));
+        assert!(!xml.contains("<file"));
+
+        let (xml, attachments) = response.to_mime(Packaging::Inline).unwrap();
+        assert!(attachments.is_empty());
+        assert!(xml.contains("AP8="));
+    }
+
+    #[test]
+    fn nil_outputs_round_trip() {
+        let mut response = Response::new();
+        response.operation = String::from("lookup");
+        response.responses.insert(String::from("found"), Part::Nil);
+        response.responses.insert(String::from("note"), Part::String(String::new()));
+
+        let xml = response.to_xml_string();
+        assert!(xml.contains("xsi:nil='true'"));
+
+        let read = Response::from_xml(xml.as_str()).unwrap();
+        assert_eq!(read.responses["found"], Part::Nil);
+        assert_eq!(read.responses["note"], Part::String(String::new()));
+    }
+
+    #[test]
+    fn array_outputs_round_trip() {
+        let mut response = Response::new();
+        response.operation = String::from("list");
+        response.responses.insert(String::from("items"), Part::Array(vec![
+            Part::String(String::from("a")),
+            Part::String(String::from("b")),
+        ]));
+
+        let xml = response.to_xml_string();
+        assert!(xml.contains("xsi:type='SOAP-ENC:Array'"));
+
+        // The prefix of the type is declared even without multi-refs.
+        let package = parser::parse(xml.as_str()).unwrap();
+        let items   = package.as_document().root().children()[0].element().unwrap()
+            .children()[0].element().unwrap()
+            .children()[0].element().unwrap()
+            .children()[0].element().unwrap();
+
+        assert_eq!(items.name().local_part(), "items");
+        assert_eq!(items.namespace_uri_for_prefix("SOAP-ENC"), Some(encoding::SOAP_ENC_NS));
+
+        let read = Response::from_xml(xml.as_str()).unwrap();
+        assert_eq!(read.responses["items"], Part::ComplexType(hashmap!{}, hashmap!{
+            String::from("item") => Part::Array(vec![
+                Part::String(String::from("a")),
+                Part::String(String::from("b")),
+            ]),
+        }));
+    }
+
+    #[test]
+    fn filtered_responses_keep_binary_outputs_inline() {
+        let mut response = download();
+        response.add_filter(Ok);
+
+        let (xml, attachments) = response.to_mime(Packaging::Attachments).unwrap();
+        assert!(attachments.is_empty());
+        assert!(xml.contains("AP8="));
+    }
+}
@@ -0,0 +1,767 @@
+use std::sync::Arc;
+
+use aes::{ Aes128, Aes256 };
+use aes::cipher::{ BlockDecryptMut, KeyIvInit };
+use aes::cipher::block_padding::NoPadding;
+use aes_gcm::{ Aes128Gcm, Aes256Gcm, Nonce };
+use aes_gcm::aead::{ Aead, KeyInit };
+use futures::future::{ self, BoxFuture, FutureExt };
+use rand::RngCore;
+use rsa::{ Oaep, RsaPrivateKey, RsaPublicKey };
+use sha1::Sha1;
+use sha2::Sha256;
+use sxd_document::{ parser, QName };
+use sxd_document::dom::{ ChildOfElement, Document, Element };
+use sxd_document::writer::format_document;
+
+use crate::c14n::C14n;
+use crate::error::SoapError;
+use crate::service::{ self, Request, Response };
+use crate::soap::{ self, Fault };
+use crate::soap::security::{ self, WSSE_NS };
+
+pub const XENC_NS:        &str = "http://www.w3.org/2001/04/xmlenc#";
+pub const XENC11_NS:      &str = "http://www.w3.org/2009/xmlenc11#";
+pub const AES128_CBC:     &str = "http://www.w3.org/2001/04/xmlenc#aes128-cbc";
+pub const AES256_CBC:     &str = "http://www.w3.org/2001/04/xmlenc#aes256-cbc";
+pub const AES128_GCM:     &str = "http://www.w3.org/2009/xmlenc11#aes128-gcm";
+pub const AES256_GCM:     &str = "http://www.w3.org/2009/xmlenc11#aes256-gcm";
+pub const RSA_OAEP:       &str = "http://www.w3.org/2009/xmlenc11#rsa-oaep";
+pub const RSA_OAEP_MGF1P: &str = "http://www.w3.org/2001/04/xmlenc#rsa-oaep-mgf1p";
+
+const CONTENT:     &str = "http://www.w3.org/2001/04/xmlenc#Content";
+const DSIG_NS:     &str = "http://www.w3.org/2000/09/xmldsig#";
+const MGF1_SHA256: &str = "http://www.w3.org/2009/xmlenc11#mgf1sha256";
+const SHA256:      &str = "http://www.w3.org/2001/04/xmlenc#sha256";
+
+/// WS-Security XML Encryption of the SOAP Body, as HTTP middleware.
+///
+/// It works on the raw envelope, so it has to be added to the HTTP server
+/// (`soap::Service::add_http_middleware`, or the shared server when
+/// mounting) rather than as SOAP middleware: requests are decrypted before
+/// they are parsed, and responses encrypted after they are written.
+///
+/// Incoming `xenc:EncryptedData` is decrypted with the key given to
+/// `decrypt_with`, using the `xenc:EncryptedKey` (RSA-OAEP) that refers to
+/// it, or the one inside its `ds:KeyInfo`. Content may use AES-CBC or
+/// AES-GCM. With `encrypt_for`, response bodies are encrypted with a fresh
+/// AES-256-GCM key for the owner of that certificate.
+///
+/// Requests that fail to decrypt, for whatever reason, all get the same
+/// `wsse:FailedCheck` fault. A response that can't be encrypted is replaced
+/// by a Server fault rather than sent as is, and so is one with MIME
+/// attachments, which are left out of the encryption: turn off `mtom` and
+/// `swa` for services that encrypt their responses.
+#[derive(Clone, Default)]
+pub struct Encryption {
+    key:       Option<Arc<RsaPrivateKey>>,
+    recipient: Option<Arc<Recipient>>,
+    required:  bool,
+}
+
+struct Recipient {
+    key:           RsaPublicKey,
+    issuer:        String,
+    serial_number: String,
+}
+
+impl Encryption {
+    pub fn new() -> Encryption {
+        Encryption::default()
+    }
+
+    /// Decrypts requests with the RSA key in the PEM file at `path`.
+    pub fn decrypt_with(mut self, path: &str) -> Result<Encryption, SoapError> {
+        self.key = Some(Arc::new(security::private_key(path)?));
+        Ok(self)
+    }
+
+    /// Encrypts response bodies for the first certificate in `path`.
+    pub fn encrypt_for(mut self, path: &str) -> Result<Encryption, SoapError> {
+        let der = match security::certificates(path)?.into_iter().next() {
+            Some(der) => der,
+            None => return Err(SoapError::Malformed(format!("No certificate found in {}", path))),
+        };
+
+        let (_, certificate) = match x509_parser::parse_x509_certificate(&der) {
+            Ok(parsed) => parsed,
+            Err(e)     => return Err(SoapError::Malformed(format!("{:?}", e))),
+        };
+
+        let key = match security::public_key(&der) {
+            Some(key) => key,
+            None => return Err(SoapError::Malformed(format!("No RSA key in the certificate in {}", path))),
+        };
+
+        self.recipient = Some(Arc::new(Recipient {
+            key:           key,
+            issuer:        certificate.issuer().to_string(),
+            serial_number: certificate.serial.to_string(),
+        }));
+
+        Ok(self)
+    }
+
+    /// Rejects requests whose Body is not encrypted: its only element has
+    /// to be an `xenc:EncryptedData`. Encrypted headers don't count.
+    pub fn required(mut self) -> Encryption {
+        self.required = true;
+        self
+    }
+
+    /// The request envelope with every `xenc:EncryptedData` replaced by
+    /// its plain text. `None` if something could not be decrypted, or if
+    /// encryption is required and the Body was sent in the clear.
+    fn decrypt(&self, xml: &str) -> Option<String> {
+        let package = parser::parse(xml).ok()?;
+        let document = package.as_document();
+
+        if self.required && !body_is_encrypted(&document) {
+            return None;
+        }
+
+        let encrypted = descendants(document.root().children().into_iter()
+            .filter_map(|c| c.element())
+            .collect(), XENC_NS, "EncryptedData");
+
+        if encrypted.is_empty() {
+            return Some(xml.to_string());
+        }
+
+        let key = self.key.as_ref()?;
+
+        for data in encrypted {
+            let content_key = self.content_key(&document, data, key)?;
+
+            let algorithm = security::child(data, Some(XENC_NS), "EncryptionMethod")?
+                .attribute_value("Algorithm")?;
+
+            let cipher_data = security::child(data, Some(XENC_NS), "CipherData")?;
+            let cipher_text = security::decode(security::child(cipher_data, Some(XENC_NS), "CipherValue")?)?;
+
+            let plain = decrypt_content(algorithm, &content_key, &cipher_text)?;
+            replace(&document, data, String::from_utf8(plain).ok()?.as_str())?;
+        }
+
+        // Keys are consumed; leave the rest of the header for later stages.
+        if let Some(header) = security::find_header(&document) {
+            for key in header.children().into_iter().filter_map(|c| c.element()) {
+                if key.name().namespace_uri() == Some(XENC_NS) && key.name().local_part() == "EncryptedKey" {
+                    key.remove_from_parent();
+                }
+            }
+        }
+
+        let mut buffer = vec![];
+        format_document(&document, &mut buffer).ok()?;
+
+        String::from_utf8(buffer).ok()
+    }
+
+    /// Symmetric key for `data`, from its own `ds:KeyInfo` or from an
+    /// `xenc:EncryptedKey` in the security header that references it.
+    fn content_key(&self, document: &Document, data: Element, key: &RsaPrivateKey) -> Option<Vec<u8>> {
+        let inline = security::child(data, Some(DSIG_NS), "KeyInfo")
+            .and_then(|info| security::child(info, Some(XENC_NS), "EncryptedKey"));
+
+        let encrypted_key = match inline {
+            Some(encrypted_key) => encrypted_key,
+            None => {
+                let id = format!("#{}", data.attribute_value("Id")?);
+
+                security::find_header(document)?.children().into_iter()
+                    .filter_map(|c| c.element())
+                    .filter(|e| e.name().namespace_uri() == Some(XENC_NS) && e.name().local_part() == "EncryptedKey")
+                    .find(|e| {
+                        security::child(*e, Some(XENC_NS), "ReferenceList")
+                            .map(|list| descendants(vec![list], XENC_NS, "DataReference").into_iter()
+                                .any(|r| r.attribute_value("URI") == Some(id.as_str())))
+                            .unwrap_or(false)
+                    })?
+            },
+        };
+
+        let method      = security::child(encrypted_key, Some(XENC_NS), "EncryptionMethod")?;
+        let cipher_data = security::child(encrypted_key, Some(XENC_NS), "CipherData")?;
+        let cipher_text = security::decode(security::child(cipher_data, Some(XENC_NS), "CipherValue")?)?;
+
+        let digest = security::child(method, Some(DSIG_NS), "DigestMethod")
+            .and_then(|d| d.attribute_value("Algorithm"));
+
+        let mgf = security::child(method, Some(XENC11_NS), "MGF")
+            .and_then(|m| m.attribute_value("Algorithm"));
+
+        let padding = match (method.attribute_value("Algorithm")?, digest, mgf) {
+            (RSA_OAEP_MGF1P, _, _)                      => Oaep::new::<Sha1>(),
+            (RSA_OAEP, Some(SHA256), Some(MGF1_SHA256)) => Oaep::new::<Sha256>(),
+            (RSA_OAEP, Some(SHA256), None)              => Oaep::new_with_mgf_hash::<Sha256, Sha1>(),
+            (RSA_OAEP, _, None)                         => Oaep::new::<Sha1>(),
+            _ => return None,
+        };
+
+        key.decrypt(padding, &cipher_text).ok()
+    }
+}
+
+impl Recipient {
+    /// Replaces the content of the response's Body with an
+    /// `xenc:EncryptedData`, and puts the `xenc:EncryptedKey` to decrypt it
+    /// first in the security header.
+    fn encrypt(&self, xml: &str) -> Option<String> {
+        let package = parser::parse(xml).ok()?;
+        let document = package.as_document();
+
+        let envelope = document.root().children().into_iter()
+            .filter_map(|c| c.element())
+            .find(|e| e.name().local_part() == "Envelope")?;
+
+        let body = security::child(envelope, None, "Body")?;
+
+        let mut plain = String::new();
+
+        for child in body.children() {
+            match child {
+                ChildOfElement::Element(element) => plain.push_str(C14n::new().element(element).as_str()),
+                ChildOfElement::Text(text)       => plain.push_str(text.text()),
+                _ => (),
+            }
+        }
+
+        let mut rng = rand::thread_rng();
+
+        let mut content_key = [0u8; 32];
+        let mut nonce       = [0u8; 12];
+        rng.fill_bytes(&mut content_key);
+        rng.fill_bytes(&mut nonce);
+
+        let cipher = Aes256Gcm::new_from_slice(&content_key).ok()?;
+        let mut cipher_text = nonce.to_vec();
+        cipher_text.extend(cipher.encrypt(Nonce::from_slice(&nonce), plain.as_bytes()).ok()?);
+
+        let wrapped_key = self.key.encrypt(&mut rng, Oaep::new::<Sha1>(), &content_key).ok()?;
+
+        let xenc = |local: &str| {
+            let element = document.create_element(QName::with_namespace_uri(Some(XENC_NS), local));
+            element.set_preferred_prefix(Some("xenc"));
+            element
+        };
+
+        let ds = |local: &str| {
+            let element = document.create_element(QName::with_namespace_uri(Some(DSIG_NS), local));
+            element.set_preferred_prefix(Some("ds"));
+            element
+        };
+
+        let cipher_data = |bytes: &[u8]| {
+            let value = xenc("CipherValue");
+            value.append_child(document.create_text(base64::encode(bytes).as_str()));
+
+            let data = xenc("CipherData");
+            data.append_child(value);
+            data
+        };
+
+        let data = xenc("EncryptedData");
+        data.register_prefix("xenc", XENC_NS);
+        data.set_attribute_value("Id", "EncryptedBody");
+        data.set_attribute_value("Type", CONTENT);
+
+        let method = xenc("EncryptionMethod");
+        method.set_attribute_value("Algorithm", AES256_GCM);
+        data.append_child(method);
+        data.append_child(cipher_data(&cipher_text));
+
+        body.clear_children();
+        body.append_child(data);
+
+        let encrypted_key = xenc("EncryptedKey");
+        encrypted_key.register_prefix("xenc", XENC_NS);
+
+        let method = xenc("EncryptionMethod");
+        method.set_attribute_value("Algorithm", RSA_OAEP_MGF1P);
+        encrypted_key.append_child(method);
+
+        let issuer_name = ds("X509IssuerName");
+        issuer_name.append_child(document.create_text(self.issuer.as_str()));
+
+        let serial_number = ds("X509SerialNumber");
+        serial_number.append_child(document.create_text(self.serial_number.as_str()));
+
+        let issuer_serial = ds("X509IssuerSerial");
+        issuer_serial.append_child(issuer_name);
+        issuer_serial.append_child(serial_number);
+
+        let x509_data = ds("X509Data");
+        x509_data.append_child(issuer_serial);
+
+        let token_ref = document.create_element(
+            QName::with_namespace_uri(Some(WSSE_NS), "SecurityTokenReference"));
+        token_ref.set_preferred_prefix(Some("wsse"));
+        token_ref.append_child(x509_data);
+
+        let key_info = ds("KeyInfo");
+        key_info.register_prefix("ds", DSIG_NS);
+        key_info.append_child(token_ref);
+        encrypted_key.append_child(key_info);
+
+        encrypted_key.append_child(cipher_data(&wrapped_key));
+
+        let reference = xenc("DataReference");
+        reference.set_attribute_value("URI", "#EncryptedBody");
+
+        let reference_list = xenc("ReferenceList");
+        reference_list.append_child(reference);
+        encrypted_key.append_child(reference_list);
+
+        // WSS 1.1 section 5: prepend, so the key is processed before any
+        // signature that was made over the encrypted Body.
+        let header   = security_header(&document, envelope);
+        let children = header.children();
+        header.clear_children();
+        header.append_child(encrypted_key);
+
+        for child in children {
+            header.append_child(child);
+        }
+
+        let mut buffer = vec![];
+        format_document(&document, &mut buffer).ok()?;
+
+        String::from_utf8(buffer).ok()
+    }
+}
+
+impl service::Middleware for Encryption {
+    fn handle<'a>(&'a self, mut request: Request, next: service::Next<'a>)
+        -> BoxFuture<'a, Response>
+    {
+        // Only SOAP calls carry envelopes; leave `GET ?wsdl` and friends alone.
+        let envelope = request.method == "POST";
+
+        if envelope && (self.key.is_some() || self.required) {
+            match self.decrypt(request.content.as_str()) {
+                Some(content) => request.content = content,
+                None => {
+                    // Every failure gets the same fault, so that a caller
+                    // can't tell bad padding from bad markup: AES-CBC has no
+                    // integrity check of its own.
+                    let fault = security::fault("FailedCheck", "The signature or decryption was invalid");
+
+                    let mut response = Response::with_status(500);
+                    response.content = fault.to_xml_string();
+                    return future::ready(response).boxed();
+                },
+            }
+        }
+
+        async move {
+            let mut response = next.run(request).await;
+
+            if let Some(ref recipient) = self.recipient {
+                if !envelope || response.content.is_empty() {
+                    return response;
+                }
+
+                // Never fall back to sending the body, or attachments next
+                // to it, in the clear.
+                let encrypted = if response.attachments.is_empty() {
+                    recipient.encrypt(response.content.as_str())
+                } else {
+                    None
+                };
+
+                response = match encrypted {
+                    Some(content) => Response {
+                        content: content,
+                        ..response
+                    },
+                    None => {
+                        let mut fault = soap::Response::new();
+                        fault.fault(Fault::server("The response could not be encrypted", "", ""));
+
+                        let mut response = Response::with_status(500);
+                        response.content = fault.to_xml_string();
+                        response
+                    },
+                };
+            }
+
+            response
+        }.boxed()
+    }
+}
+
+/// Whether the only element in the envelope's Body is an
+/// `xenc:EncryptedData`.
+fn body_is_encrypted(document: &Document) -> bool {
+    let body = document.root().children().into_iter()
+        .filter_map(|c| c.element())
+        .find(|e| e.name().local_part() == "Envelope")
+        .and_then(|envelope| security::child(envelope, None, "Body"));
+
+    let children: Vec<Element> = match body {
+        Some(body) => body.children().into_iter().filter_map(|c| c.element()).collect(),
+        None       => return false,
+    };
+
+    match children.as_slice() {
+        [data] => data.name().namespace_uri() == Some(XENC_NS) && data.name().local_part() == "EncryptedData",
+        _      => false,
+    }
+}
+
+fn decrypt_content(algorithm: &str, key: &[u8], data: &[u8]) -> Option<Vec<u8>> {
+    match algorithm {
+        AES128_GCM | AES256_GCM => {
+            if data.len() < 12 {
+                return None;
+            }
+
+            let (nonce, cipher_text) = data.split_at(12);
+            let nonce = Nonce::from_slice(nonce);
+
+            match algorithm {
+                AES128_GCM => Aes128Gcm::new_from_slice(key).ok()?.decrypt(nonce, cipher_text).ok(),
+                _          => Aes256Gcm::new_from_slice(key).ok()?.decrypt(nonce, cipher_text).ok(),
+            }
+        },
+        AES128_CBC | AES256_CBC => {
+            if data.len() < 32 || data.len() % 16 != 0 {
+                return None;
+            }
+
+            let (iv, cipher_text) = data.split_at(16);
+
+            let mut plain = match algorithm {
+                AES128_CBC => cbc::Decryptor::<Aes128>::new_from_slices(key, iv).ok()?
+                    .decrypt_padded_vec_mut::<NoPadding>(cipher_text).ok()?,
+                _          => cbc::Decryptor::<Aes256>::new_from_slices(key, iv).ok()?
+                    .decrypt_padded_vec_mut::<NoPadding>(cipher_text).ok()?,
+            };
+
+            // XML Encryption pads like ISO 10126: only the last byte counts.
+            let padding = *plain.last()? as usize;
+
+            if padding == 0 || padding > 16 {
+                return None;
+            }
+
+            let length = plain.len() - padding;
+            plain.truncate(length);
+            Some(plain)
+        },
+        _ => None,
+    }
+}
+
+/// Puts the decrypted markup where `data` was. The fragment is parsed in a
+/// wrapper carrying the namespaces in scope at `data`, so prefixes declared
+/// further up the envelope still resolve.
+fn replace(document: &Document, data: Element, plain: &str) -> Option<()> {
+    let parent = data.parent()?.element()?;
+
+    let mut wrapper = String::from("<wrapper");
+
+    for namespace in parent.namespaces_in_scope() {
+        if namespace.prefix() == "xml" {
+            continue;
+        }
+
+        wrapper.push_str(format!(" xmlns:{}=\"{}\"", namespace.prefix(), namespace.uri()).as_str());
+    }
+
+    if let Some(uri) = parent.recursive_default_namespace_uri() {
+        wrapper.push_str(format!(" xmlns=\"{}\"", uri).as_str());
+    }
+
+    wrapper.push('>');
+    wrapper.push_str(plain);
+    wrapper.push_str("</wrapper>");
+
+    let package  = parser::parse(wrapper.as_str()).ok()?;
+    let fragment = package.as_document();
+    let wrapper  = fragment.root().children().into_iter().filter_map(|c| c.element()).next()?;
+
+    let children = parent.children();
+    parent.clear_children();
+
+    for child in children {
+        match child {
+            ChildOfElement::Element(element) if element == data => {
+                for node in wrapper.children() {
+                    if let Some(node) = import(document, node) {
+                        parent.append_child(node);
+                    }
+                }
+            },
+            _ => parent.append_child(child),
+        }
+    }
+
+    Some(())
+}
+
+/// Copies a node from another document into `document`.
+fn import<'d>(document: &Document<'d>, node: ChildOfElement) -> Option<ChildOfElement<'d>> {
+    match node {
+        ChildOfElement::Element(element) => {
+            let name = element.name();
+            let copy = document.create_element(QName::with_namespace_uri(name.namespace_uri(), name.local_part()));
+            copy.set_preferred_prefix(element.preferred_prefix());
+            copy.set_default_namespace_uri(element.default_namespace_uri());
+
+            for attr in element.attributes() {
+                let name = attr.name();
+                let copied = copy.set_attribute_value(
+                    QName::with_namespace_uri(name.namespace_uri(), name.local_part()),
+                    attr.value());
+                copied.set_preferred_prefix(attr.preferred_prefix());
+            }
+
+            for child in element.children() {
+                if let Some(child) = import(document, child) {
+                    copy.append_child(child);
+                }
+            }
+
+            Some(copy.into())
+        },
+        ChildOfElement::Text(text)                => Some(document.create_text(text.text()).into()),
+        ChildOfElement::Comment(comment)          => Some(document.create_comment(comment.text()).into()),
+        ChildOfElement::ProcessingInstruction(pi) => {
+            Some(document.create_processing_instruction(pi.target(), pi.value()).into())
+        },
+    }
+}
+
+/// Every element named `local` in `ns` under `roots`, in document order.
+fn descendants<'d>(roots: Vec<Element<'d>>, ns: &str, local: &str) -> Vec<Element<'d>> {
+    let mut found   = vec![];
+    let mut pending = roots;
+    pending.reverse();
+
+    while let Some(element) = pending.pop() {
+        if element.name().namespace_uri() == Some(ns) && element.name().local_part() == local {
+            found.push(element);
+            continue;
+        }
+
+        let mut children: Vec<Element> = element.children().into_iter()
+            .filter_map(|c| c.element())
+            .collect();
+
+        children.reverse();
+        pending.extend(children);
+    }
+
+    found
+}
+
+/// The envelope's `wsse:Security` block, created (along with the Header)
+/// when the response has none.
+fn security_header<'d>(document: &Document<'d>, envelope: Element<'d>) -> Element<'d> {
+    if let Some(header) = security::find_header(document) {
+        return header;
+    }
+
+    let header = match security::child(envelope, None, "Header") {
+        Some(header) => header,
+        None => {
+            let name   = envelope.name();
+            let header = document.create_element(QName::with_namespace_uri(name.namespace_uri(), "Header"));
+            header.set_preferred_prefix(envelope.preferred_prefix());
+
+            let children = envelope.children();
+            envelope.clear_children();
+            envelope.append_child(header);
+
+            for child in children {
+                envelope.append_child(child);
+            }
+
+            header
+        },
+    };
+
+    let wsse = document.create_element(QName::with_namespace_uri(Some(WSSE_NS), "Security"));
+    wsse.set_preferred_prefix(Some("wsse"));
+    wsse.register_prefix("wsse", WSSE_NS);
+    header.append_child(wsse);
+    wsse
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use aes::cipher::BlockEncryptMut;
+    use futures::executor::block_on;
+
+    use crate::service::MimePart;
+
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/cert.pem");
+    const KEY:  &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/key.pem");
+
+    /// Runs `encryption` on a request whose handler echoes what it receives.
+    fn post(encryption: Encryption, content: String) -> Response {
+        let middleware: Vec<Arc<dyn service::Middleware>> = vec![Arc::new(encryption)];
+        let handler: service::Handler = Arc::new(|request: Request| {
+            future::ready(Response::new(hashmap!{}, request.content)).boxed()
+        });
+
+        let request = Request {
+            method:  String::from("POST"),
+            content: content,
+            ..Request::default()
+        };
+
+        block_on(service::Next::new(&middleware, &handler).run(request))
+    }
+
+    fn signed_response() -> String {
+        let mut response = soap::Response::new();
+        response.operation = String::from("echo");
+        response.responses.insert(String::from("value"), soap::Part::String(String::from("secret")));
+        response.add_security_header(|document| {
+            let signature = document.create_element(QName::with_namespace_uri(Some(DSIG_NS), "Signature"));
+            signature.set_preferred_prefix(Some("ds"));
+            signature.register_prefix("ds", DSIG_NS);
+            signature
+        });
+
+        response.to_xml_string()
+    }
+
+    /// An envelope whose Body was encrypted with AES-128-CBC, the way older
+    /// stacks do, with `padding` as the last byte of the padded plain text.
+    fn cbc_request(plain: &str, padding: u8) -> String {
+        let der = security::certificates(CERT).unwrap().remove(0);
+        let key = security::public_key(&der).unwrap();
+
+        let content_key = [7u8; 16];
+        let iv          = [9u8; 16];
+
+        let mut padded = plain.as_bytes().to_vec();
+        let fill = 16 - padded.len() % 16;
+        padded.extend(vec![0u8; fill - 1]);
+        padded.push(padding);
+
+        let mut cipher_text = iv.to_vec();
+        cipher_text.extend(cbc::Encryptor::<Aes128>::new_from_slices(&content_key, &iv).unwrap()
+            .encrypt_padded_vec_mut::<NoPadding>(&padded));
+
+        let wrapped = key.encrypt(&mut rand::thread_rng(), Oaep::new::<Sha1>(), &content_key).unwrap();
+
+        format!(concat!(
+            r#"<soapenv:Envelope xmlns:soapenv="http://schemas.xmlsoap.org/soap/envelope/">"#,
+            r#"<soapenv:Body>"#,
+            r#"<xenc:EncryptedData xmlns:xenc="{xenc}" Type="{content}">"#,
+            r#"<xenc:EncryptionMethod Algorithm="{cbc}"/>"#,
+            r#"<ds:KeyInfo xmlns:ds="{ds}"><xenc:EncryptedKey>"#,
+            r#"<xenc:EncryptionMethod Algorithm="{oaep}"/>"#,
+            r#"<xenc:CipherData><xenc:CipherValue>{key}</xenc:CipherValue></xenc:CipherData>"#,
+            r#"</xenc:EncryptedKey></ds:KeyInfo>"#,
+            r#"<xenc:CipherData><xenc:CipherValue>{data}</xenc:CipherValue></xenc:CipherData>"#,
+            r#"</xenc:EncryptedData>"#,
+            r#"</soapenv:Body>"#,
+            r#"</soapenv:Envelope>"#),
+            xenc    = XENC_NS,
+            content = CONTENT,
+            cbc     = AES128_CBC,
+            ds      = DSIG_NS,
+            oaep    = RSA_OAEP_MGF1P,
+            key     = base64::encode(&wrapped),
+            data    = base64::encode(&cipher_text))
+    }
+
+    #[test]
+    fn encrypts_responses_for_the_recipient() {
+        let response = post(Encryption::new().encrypt_for(CERT).unwrap(), signed_response());
+
+        assert!(!response.content.contains("secret"));
+
+        // The key goes first, ahead of the signature already in the header.
+        let key       = response.content.find("EncryptedKey").unwrap();
+        let signature = response.content.find("Signature").unwrap();
+        assert!(key < signature);
+
+        let plain = Encryption::new().decrypt_with(KEY).unwrap()
+            .decrypt(response.content.as_str())
+            .unwrap();
+
+        assert!(plain.contains("secret"));
+        assert!(!plain.contains("EncryptedKey"));
+    }
+
+    #[test]
+    fn never_sends_what_it_could_not_encrypt() {
+        let response = post(Encryption::new().encrypt_for(CERT).unwrap(), String::from("secret <unclosed"));
+
+        assert!(!response.content.contains("secret"));
+        assert!(response.content.contains("could not be encrypted"));
+        assert_eq!(response.header.get("status-code").map(|s| s.as_str()), Some("500"));
+    }
+
+    #[test]
+    fn never_sends_attachments_in_the_clear() {
+        let encryption = Encryption::new().encrypt_for(CERT).unwrap();
+        let middleware: Vec<Arc<dyn service::Middleware>> = vec![Arc::new(encryption)];
+        let handler: service::Handler = Arc::new(|_| {
+            let mut response = Response::new(hashmap!{}, signed_response());
+            response.attachments.push(MimePart::new("file=0@test", "text/plain", "secret"));
+            future::ready(response).boxed()
+        });
+
+        let request = Request {
+            method: String::from("POST"),
+            ..Request::default()
+        };
+
+        let response = block_on(service::Next::new(&middleware, &handler).run(request));
+
+        assert!(response.attachments.is_empty());
+        assert!(!response.content.contains("secret"));
+        assert!(response.content.contains("could not be encrypted"));
+    }
+
+    #[test]
+    fn decrypts_cbc_bodies() {
+        let response = post(Encryption::new().decrypt_with(KEY).unwrap(), cbc_request("<echo>hi</echo>", 1));
+
+        assert!(response.content.contains("<echo>hi</echo>"));
+        assert!(!response.content.contains("EncryptedData"));
+    }
+
+    #[test]
+    fn requires_an_encrypted_body() {
+        let requiring = || Encryption::new().decrypt_with(KEY).unwrap().required();
+
+        let encrypted = post(requiring(), cbc_request("<echo>hi</echo>", 1));
+        assert!(encrypted.content.contains("<echo>hi</echo>"));
+
+        // An encrypted header doesn't make up for a plain text Body.
+        let plain = cbc_request("<token>x</token>", 1)
+            .replace("<soapenv:Body>", "<soapenv:Header>")
+            .replace("</soapenv:Body>", "</soapenv:Header><soapenv:Body><echo>plain</echo></soapenv:Body>");
+
+        let response = post(requiring(), plain.clone());
+        assert!(response.content.contains("FailedCheck"));
+        assert!(!response.content.contains("plain"));
+
+        let response = post(Encryption::new().decrypt_with(KEY).unwrap(), plain);
+        assert!(response.content.contains("<echo>plain</echo>"));
+    }
+
+    #[test]
+    fn cbc_failures_all_look_the_same() {
+        let decrypting = || Encryption::new().decrypt_with(KEY).unwrap();
+
+        let bad_padding = post(decrypting(), cbc_request("<echo>hi</echo>", 0));
+        let bad_markup  = post(decrypting(), cbc_request("<echo>hi</ech", 1));
+
+        assert_eq!(bad_markup.content, bad_padding.content);
+        assert_eq!(bad_markup.header, bad_padding.header);
+
+        assert!(bad_padding.content.contains("FailedCheck"));
+    }
+}
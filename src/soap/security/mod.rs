@@ -0,0 +1,142 @@
+//! WS-Security (OASIS Web Services Security 1.0) support.
+
+mod encryption;
+mod signature;
+mod timestamp;
+mod username_token;
+
+pub use self::encryption::Encryption;
+pub use self::signature::Signature;
+pub use self::timestamp::Timestamp;
+pub use self::username_token::{ CredentialStore, UsernameToken, UsernameTokenAuth };
+
+use std::fs::File;
+use std::io::BufReader;
+
+use rsa::{ RsaPrivateKey, RsaPublicKey };
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::{ DecodePrivateKey, DecodePublicKey };
+use rustls_pemfile::Item;
+use sxd_document::dom::{ Document, Element };
+
+use crate::error::SoapError;
+use crate::soap::{ Fault, Response };
+
+pub const WSSE_NS: &str = "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-secext-1.0.xsd";
+pub const WSU_NS:  &str = "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-wssecurity-utility-1.0.xsd";
+
+/// Creates an empty `wsse:Security` header block, to be filled with tokens
+/// and appended to the envelope's `SOAP-ENV:Header`.
+pub fn header<'d>(document: &Document<'d>) -> Element<'d> {
+    let security = document.create_element("wsse:Security");
+    security.set_attribute_value("xmlns:wsse", WSSE_NS);
+    security.set_attribute_value("xmlns:wsu", WSU_NS);
+    security.set_attribute_value("SOAP-ENV:mustUnderstand", "1");
+    security
+}
+
+/// Response carrying one of the faults defined by WS-Security, such as
+/// `FailedAuthentication`.
+pub fn fault(name: &str, string: &str) -> Response {
+    let mut response = Response::new();
+    response.fault(Fault::qualified("wsse", WSSE_NS, name, string));
+    response
+}
+
+/// The `wsse:Security` block of a parsed envelope, if any.
+pub fn find_header<'d>(document: &Document<'d>) -> Option<Element<'d>> {
+    let envelope = document.root().children().into_iter()
+        .filter_map(|c| c.element())
+        .find(|e| e.name().local_part() == "Envelope")?;
+
+    let header = child(envelope, None, "Header")?;
+    child(header, Some(WSSE_NS), "Security")
+}
+
+/// First child element named `local`, in namespace `ns` when given.
+pub fn child<'d>(element: Element<'d>, ns: Option<&str>, local: &str) -> Option<Element<'d>> {
+    element.children().into_iter()
+        .filter_map(|c| c.element())
+        .find(|e| {
+            e.name().local_part() == local
+                && (ns.is_none() || e.name().namespace_uri() == ns)
+        })
+}
+
+/// Concatenated text content of an element's direct children.
+pub fn text(element: Element) -> String {
+    element.children().iter()
+        .filter_map(|c| c.text())
+        .map(|t| t.text())
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Element whose `Id` (usually `wsu:Id`) matches a same-document
+/// reference such as `#Body`.
+fn find_by_id<'d>(document: &Document<'d>, uri: &str) -> Option<Element<'d>> {
+    if !uri.starts_with('#') {
+        return None;
+    }
+
+    let id = &uri[1..];
+    let mut pending: Vec<Element> = document.root().children().into_iter()
+        .filter_map(|c| c.element())
+        .collect();
+
+    while let Some(element) = pending.pop() {
+        let matches = element.attributes().iter()
+            .any(|a| a.name().local_part() == "Id" && a.value() == id);
+
+        if matches {
+            return Some(element);
+        }
+
+        pending.extend(element.children().into_iter().filter_map(|c| c.element()));
+    }
+
+    None
+}
+
+/// Base64 content of an element, ignoring line breaks.
+fn decode(element: Element) -> Option<Vec<u8>> {
+    let text: String = text(element).split_whitespace().collect();
+    base64::decode(text).ok()
+}
+
+/// DER certificates in a PEM file.
+fn certificates(path: &str) -> Result<Vec<Vec<u8>>, SoapError> {
+    Ok(read_pem(path)?.into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(der),
+            _ => None,
+        })
+        .collect())
+}
+
+/// First RSA key in a PEM file, in PKCS#8 or PKCS#1 form.
+fn private_key(path: &str) -> Result<RsaPrivateKey, SoapError> {
+    let key = read_pem(path)?.into_iter()
+        .filter_map(|item| match item {
+            Item::PKCS8Key(der) => RsaPrivateKey::from_pkcs8_der(&der).ok(),
+            Item::RSAKey(der)   => RsaPrivateKey::from_pkcs1_der(&der).ok(),
+            _ => None,
+        })
+        .next();
+
+    match key {
+        Some(key) => Ok(key),
+        None      => Err(SoapError::Malformed(format!("No RSA key found in {}", path))),
+    }
+}
+
+fn public_key(certificate: &[u8]) -> Option<RsaPublicKey> {
+    let (_, certificate) = x509_parser::parse_x509_certificate(certificate).ok()?;
+    RsaPublicKey::from_public_key_der(certificate.public_key().raw).ok()
+}
+
+fn read_pem(path: &str) -> Result<Vec<Item>, SoapError> {
+    let mut reader = BufReader::new(File::open(path)?);
+    Ok(rustls_pemfile::read_all(&mut reader)?)
+}
@@ -0,0 +1,250 @@
+use chrono::{ DateTime, Duration, SecondsFormat, Utc };
+use futures::future::{ self, BoxFuture, FutureExt };
+use sxd_document::parser;
+use sxd_document::dom::{ Document, Element };
+
+use crate::soap::{ Middleware, Next, Request, Response };
+use crate::soap::security::{ self, WSU_NS };
+
+/// `wsu:Timestamp` handling, as SOAP middleware.
+///
+/// Incoming messages whose timestamp has expired, or was created further in
+/// the future than `clock_skew` allows, get a `wsse:MessageExpired` fault.
+/// Messages without a timestamp pass unless it is `required`. Every
+/// response is stamped with a timestamp valid for `ttl`.
+#[derive(Clone, Debug)]
+pub struct Timestamp {
+    ttl:        Duration,
+    clock_skew: Duration,
+    required:   bool,
+}
+
+impl Default for Timestamp {
+    fn default() -> Timestamp {
+        Timestamp::new()
+    }
+}
+
+impl Timestamp {
+    pub fn new() -> Timestamp {
+        Timestamp {
+            ttl:        Duration::minutes(5),
+            clock_skew: Duration::minutes(1),
+            required:   false,
+        }
+    }
+
+    pub fn ttl(mut self, seconds: i64) -> Timestamp {
+        self.ttl = Duration::seconds(seconds);
+        self
+    }
+
+    pub fn clock_skew(mut self, seconds: i64) -> Timestamp {
+        self.clock_skew = Duration::seconds(seconds);
+        self
+    }
+
+    /// Rejects messages without a `wsu:Timestamp`.
+    pub fn required(mut self) -> Timestamp {
+        self.required = true;
+        self
+    }
+
+    /// Builds a `wsu:Timestamp` created now and expiring after `ttl`.
+    pub fn to_xml<'d>(&self, document: &Document<'d>) -> Element<'d> {
+        let created = Utc::now();
+        let expires = created + self.ttl;
+
+        timestamp(document, format_time(created).as_str(), format_time(expires).as_str())
+    }
+
+    fn check(&self, xml: &str) -> bool {
+        let package = match parser::parse(xml) {
+            Ok(package) => package,
+            Err(_)      => return false,
+        };
+
+        let document = package.as_document();
+
+        let stamp = security::find_header(&document)
+            .and_then(|header| security::child(header, Some(WSU_NS), "Timestamp"));
+
+        let stamp = match stamp {
+            Some(stamp) => stamp,
+            None        => return !self.required,
+        };
+
+        let now = Utc::now();
+
+        let created = security::child(stamp, Some(WSU_NS), "Created")
+            .map(security::text)
+            .and_then(|created| parse_time(created.as_str()));
+
+        let expires = security::child(stamp, Some(WSU_NS), "Expires")
+            .map(|expires| parse_time(security::text(expires).as_str()));
+
+        match created {
+            Some(created) if created <= now + self.clock_skew => (),
+            _ => return false,
+        }
+
+        match expires {
+            Some(Some(expires)) => now - self.clock_skew <= expires,
+            Some(None)          => false,
+            None                => true,
+        }
+    }
+}
+
+impl Middleware for Timestamp {
+    fn handle<'a>(&'a self, request: Request, next: Next<'a>)
+        -> BoxFuture<'a, Response>
+    {
+        if !self.check(request.context.raw_xml.as_str()) {
+            return future::ready(security::fault(
+                "MessageExpired", "The message has expired")).boxed();
+        }
+
+        async move {
+            let mut response = next.run(request).await;
+
+            let created = Utc::now();
+            let expires = format_time(created + self.ttl);
+            let created = format_time(created);
+
+            response.add_security_header(move |document| {
+                timestamp(document, created.as_str(), expires.as_str())
+            });
+
+            response
+        }.boxed()
+    }
+}
+
+fn timestamp<'d>(document: &Document<'d>, created: &str, expires: &str) -> Element<'d> {
+    let stamp = document.create_element("wsu:Timestamp");
+    stamp.set_attribute_value("wsu:Id", "Timestamp");
+
+    let created_elem = document.create_element("wsu:Created");
+    created_elem.append_child(document.create_text(created));
+    stamp.append_child(created_elem);
+
+    let expires_elem = document.create_element("wsu:Expires");
+    expires_elem.append_child(document.create_text(expires));
+    stamp.append_child(expires_elem);
+
+    stamp
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time).ok().map(|t| t.with_timezone(&Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use futures::executor::block_on;
+
+    use crate::soap::Context;
+    use crate::soap::middleware::Dispatch;
+
+    /// An envelope stamped as created and expiring `created` and `expires`
+    /// seconds from now, or without a timestamp.
+    fn envelope(stamp: Option<(i64, i64)>) -> String {
+        let header = match stamp {
+            Some((created, expires)) => {
+                let now = Utc::now();
+
+                format!(concat!(
+                    r#"<SOAP-ENV:Header><wsse:Security xmlns:wsse="{}" xmlns:wsu="{}">"#,
+                    r#"<wsu:Timestamp><wsu:Created>{}</wsu:Created><wsu:Expires>{}</wsu:Expires></wsu:Timestamp>"#,
+                    r#"</wsse:Security></SOAP-ENV:Header>"#),
+                    security::WSSE_NS, WSU_NS,
+                    format_time(now + Duration::seconds(created)),
+                    format_time(now + Duration::seconds(expires)))
+            },
+            None => String::new(),
+        };
+
+        format!(concat!(
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">"#,
+            r#"{}<SOAP-ENV:Body><op/></SOAP-ENV:Body></SOAP-ENV:Envelope>"#), header)
+    }
+
+    fn run(timestamp: &Timestamp, xml: String) -> Response {
+        let request = Request {
+            operation: String::from("op"),
+            arguments: hashmap!{},
+            context:   Context {
+                raw_xml: xml,
+                ..Context::default()
+            },
+        };
+
+        let dispatch: &Dispatch = &|_| future::ready(Response::new()).boxed();
+
+        block_on(timestamp.handle(request, Next::new(&[], dispatch)))
+    }
+
+    #[test]
+    fn accepts_fresh_timestamps() {
+        let timestamp = Timestamp::new();
+
+        assert!(timestamp.check(envelope(Some((0, 300))).as_str()));
+        // Within the allowed clock skew either way.
+        assert!(timestamp.check(envelope(Some((30, 300))).as_str()));
+        assert!(timestamp.check(envelope(Some((-300, -30))).as_str()));
+    }
+
+    #[test]
+    fn rejects_expired_timestamps() {
+        let timestamp = Timestamp::new();
+
+        assert!(!timestamp.check(envelope(Some((-600, -300))).as_str()));
+
+        let response = run(&timestamp, envelope(Some((-600, -300))));
+        assert!(response.get_fault().map(|fault| fault.code()).unwrap().ends_with("MessageExpired"));
+    }
+
+    #[test]
+    fn rejects_timestamps_created_in_the_future() {
+        let timestamp = Timestamp::new().clock_skew(10);
+
+        assert!(!timestamp.check(envelope(Some((60, 360))).as_str()));
+        assert!(timestamp.check(envelope(Some((5, 360))).as_str()));
+    }
+
+    #[test]
+    fn can_require_a_timestamp() {
+        assert!(Timestamp::new().check(envelope(None).as_str()));
+        assert!(!Timestamp::new().required().check(envelope(None).as_str()));
+
+        let response = run(&Timestamp::new().required(), envelope(None));
+        assert!(response.get_fault().is_some());
+    }
+
+    #[test]
+    fn stamps_responses() {
+        let response = run(&Timestamp::new().ttl(60), envelope(Some((0, 300))));
+        assert!(response.get_fault().is_none());
+
+        let xml     = response.to_xml_string();
+        let package = parser::parse(xml.as_str()).unwrap();
+        let stamp   = security::find_header(&package.as_document())
+            .and_then(|header| security::child(header, Some(WSU_NS), "Timestamp"))
+            .expect("a timestamp");
+
+        let time = |name| {
+            let element = security::child(stamp, Some(WSU_NS), name).unwrap();
+            parse_time(security::text(element).as_str()).unwrap()
+        };
+
+        assert_eq!(time("Expires") - time("Created"), Duration::seconds(60));
+        assert!(time("Created") <= Utc::now());
+    }
+}
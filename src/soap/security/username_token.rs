@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+use chrono::{ DateTime, Duration, SecondsFormat, Utc };
+use futures::future::{ self, BoxFuture, FutureExt };
+use rand::RngCore;
+use sha1::{ Digest, Sha1 };
+use sxd_document::parser;
+use sxd_document::dom::{ Document, Element };
+
+use crate::service::{ constant_time_eq, Principal };
+use crate::soap::{ Middleware, Next, Request, Response };
+use crate::soap::security::{ self, WSSE_NS, WSU_NS };
+
+pub const PASSWORD_TEXT:   &str = "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordText";
+pub const PASSWORD_DIGEST: &str = "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-username-token-profile-1.0#PasswordDigest";
+pub const BASE64_BINARY:   &str = "http://docs.oasis-open.org/wss/2004/01/oasis-200401-wss-soap-message-security-1.0#Base64Binary";
+
+/// Where `UsernameTokenAuth` looks up passwords. Any
+/// `Fn(&str) -> Option<String>` closure works as one.
+pub trait CredentialStore: Send + Sync + 'static {
+    /// Clear-text password of `username`, or `None` if there is no such
+    /// user. PasswordDigest needs the actual password, not a hash of it.
+    fn password(&self, username: &str) -> Option<String>;
+}
+
+impl<F> CredentialStore for F
+    where F: Fn(&str) -> Option<String> + Send + Sync + 'static
+{
+    fn password(&self, username: &str) -> Option<String> {
+        self(username)
+    }
+}
+
+/// Client side of the UsernameToken profile: produces the token to put
+/// inside a `wsse:Security` header.
+#[derive(Clone, Debug)]
+pub struct UsernameToken {
+    pub username: String,
+    pub password: String,
+    pub digest:   bool,
+}
+
+impl UsernameToken {
+    /// Sends the password as is. Only use it over TLS.
+    pub fn text(username: &str, password: &str) -> UsernameToken {
+        UsernameToken {
+            username: username.to_string(),
+            password: password.to_string(),
+            digest:   false,
+        }
+    }
+
+    /// Sends `Base64(SHA-1(nonce + created + password))` along with a
+    /// fresh nonce and creation time.
+    pub fn digest(username: &str, password: &str) -> UsernameToken {
+        UsernameToken {
+            digest: true,
+            ..UsernameToken::text(username, password)
+        }
+    }
+
+    /// Builds the `wsse:UsernameToken` element. Every call uses a new nonce
+    /// and timestamp, so build one per message.
+    pub fn to_xml<'d>(&self, document: &Document<'d>) -> Element<'d> {
+        let token = document.create_element("wsse:UsernameToken");
+
+        let username = document.create_element("wsse:Username");
+        username.append_child(document.create_text(self.username.as_str()));
+        token.append_child(username);
+
+        let password = document.create_element("wsse:Password");
+
+        if self.digest {
+            let mut nonce = [0u8; 16];
+            rand::thread_rng().fill_bytes(&mut nonce);
+
+            let created = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+            let digest  = password_digest(&nonce, created.as_str(), self.password.as_str());
+
+            password.set_attribute_value("Type", PASSWORD_DIGEST);
+            password.append_child(document.create_text(digest.as_str()));
+            token.append_child(password);
+
+            let nonce_elem = document.create_element("wsse:Nonce");
+            nonce_elem.set_attribute_value("EncodingType", BASE64_BINARY);
+            nonce_elem.append_child(document.create_text(base64::encode(nonce).as_str()));
+            token.append_child(nonce_elem);
+
+            let created_elem = document.create_element("wsu:Created");
+            created_elem.append_child(document.create_text(created.as_str()));
+            token.append_child(created_elem);
+        } else {
+            password.set_attribute_value("Type", PASSWORD_TEXT);
+            password.append_child(document.create_text(self.password.as_str()));
+            token.append_child(password);
+        }
+
+        token
+    }
+}
+
+/// Server side of the UsernameToken profile, as SOAP middleware.
+///
+/// Requests without a valid token are answered with a
+/// `wsse:FailedAuthentication` fault. Tokens whose `Created` is older than
+/// `max_age` (or ahead of the server's clock by more than `clock_skew`) are
+/// rejected, and so are nonces already seen within that window. On success
+/// the caller's `Principal` is put in the request context.
+pub struct UsernameTokenAuth {
+    store:          Box<dyn CredentialStore>,
+    max_age:        Duration,
+    clock_skew:     Duration,
+    require_digest: bool,
+    nonces:         Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl UsernameTokenAuth {
+    pub fn new<S: CredentialStore>(store: S) -> UsernameTokenAuth {
+        UsernameTokenAuth {
+            store:          Box::new(store),
+            max_age:        Duration::minutes(5),
+            clock_skew:     Duration::minutes(1),
+            require_digest: false,
+            nonces:         Mutex::new(hashmap!{}),
+        }
+    }
+
+    pub fn max_age(mut self, seconds: i64) -> UsernameTokenAuth {
+        self.max_age = Duration::seconds(seconds);
+        self
+    }
+
+    pub fn clock_skew(mut self, seconds: i64) -> UsernameTokenAuth {
+        self.clock_skew = Duration::seconds(seconds);
+        self
+    }
+
+    /// Refuses PasswordText tokens.
+    pub fn require_digest(mut self) -> UsernameTokenAuth {
+        self.require_digest = true;
+        self
+    }
+
+    fn validate(&self, xml: &str) -> Option<Principal> {
+        let package  = parser::parse(xml).ok()?;
+        let document = package.as_document();
+        let header   = security::find_header(&document)?;
+        let token    = security::child(header, Some(WSSE_NS), "UsernameToken")?;
+
+        let username = security::text(security::child(token, Some(WSSE_NS), "Username")?);
+        let password = security::child(token, Some(WSSE_NS), "Password")?;
+        let nonce    = security::child(token, Some(WSSE_NS), "Nonce").map(security::text);
+        let created  = security::child(token, Some(WSU_NS), "Created").map(security::text);
+
+        let kind     = password.attribute_value("Type").unwrap_or(PASSWORD_TEXT).to_string();
+        let password = security::text(password);
+        let expected = self.store.password(username.as_str())?;
+
+        let created_at = match created {
+            Some(ref created) => Some(self.check_created(created.as_str())?),
+            None              => None,
+        };
+
+        let valid = if kind == PASSWORD_DIGEST {
+            let nonce_bytes = base64::decode(nonce.as_ref()?.as_str()).ok()?;
+            let created     = created.as_ref()?;
+
+            let digest = password_digest(&nonce_bytes, created.as_str(), expected.as_str());
+            constant_time_eq(digest.as_bytes(), password.as_bytes())
+        } else if kind == PASSWORD_TEXT {
+            !self.require_digest && constant_time_eq(password.as_bytes(), expected.as_bytes())
+        } else {
+            false
+        };
+
+        // Nonces are only recorded for valid tokens, so garbage can't be used
+        // to lock legitimate clients out.
+        if !valid {
+            return None;
+        }
+
+        if let (Some(nonce), Some(created_at)) = (nonce, created_at) {
+            if !self.check_nonce(nonce, created_at) {
+                return None;
+            }
+        }
+
+        Some(Principal {
+            name:   username,
+            scheme: String::from("UsernameToken"),
+        })
+    }
+
+    fn check_created(&self, created: &str) -> Option<DateTime<Utc>> {
+        let created = DateTime::parse_from_rfc3339(created).ok()?.with_timezone(&Utc);
+        let now     = Utc::now();
+
+        if created > now + self.clock_skew || now - created > self.max_age + self.clock_skew {
+            return None;
+        }
+
+        Some(created)
+    }
+
+    fn check_nonce(&self, nonce: String, created: DateTime<Utc>) -> bool {
+        let mut nonces = self.nonces.lock().unwrap();
+        let     oldest = Utc::now() - self.max_age - self.clock_skew;
+
+        // Anything older would fail `check_created` anyway.
+        nonces.retain(|_, seen| *seen >= oldest);
+
+        if nonces.contains_key(&nonce) {
+            return false;
+        }
+
+        nonces.insert(nonce, created);
+        true
+    }
+}
+
+impl Middleware for UsernameTokenAuth {
+    fn handle<'a>(&'a self, mut request: Request, next: Next<'a>)
+        -> BoxFuture<'a, Response>
+    {
+        match self.validate(request.context.raw_xml.as_str()) {
+            Some(principal) => {
+                request.context.insert(principal);
+                next.run(request)
+            },
+            None => future::ready(security::fault(
+                "FailedAuthentication",
+                "The security token could not be authenticated or authorized"
+            )).boxed(),
+        }
+    }
+}
+
+fn password_digest(nonce: &[u8], created: &str, password: &str) -> String {
+    let mut hash = Sha1::new();
+    hash.update(nonce);
+    hash.update(created.as_bytes());
+    hash.update(password.as_bytes());
+
+    base64::encode(hash.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::soap::Client;
+
+    fn auth() -> UsernameTokenAuth {
+        UsernameTokenAuth::new(|user: &str| match user {
+            "alice" => Some(String::from("secret")),
+            _       => None,
+        })
+    }
+
+    fn envelope(token: UsernameToken) -> String {
+        let mut client = Client::new("http://localhost/", "test");
+        client.set_username_token(token);
+        client.envelope("op", &hashmap!{})
+    }
+
+    #[test]
+    fn accepts_text_and_digest_passwords() {
+        let auth = auth();
+
+        let principal = auth.validate(envelope(UsernameToken::text("alice", "secret")).as_str());
+        assert_eq!(principal.map(|p| p.name), Some(String::from("alice")));
+
+        assert!(auth.validate(envelope(UsernameToken::digest("alice", "secret")).as_str()).is_some());
+        assert!(auth.validate(envelope(UsernameToken::digest("alice", "wrong")).as_str()).is_none());
+        assert!(auth.validate(envelope(UsernameToken::text("mallory", "secret")).as_str()).is_none());
+    }
+
+    #[test]
+    fn rejects_replayed_nonces() {
+        let auth = auth();
+        let xml  = envelope(UsernameToken::digest("alice", "secret"));
+
+        assert!(auth.validate(xml.as_str()).is_some());
+        assert!(auth.validate(xml.as_str()).is_none());
+    }
+
+    #[test]
+    fn can_require_digests() {
+        let auth = auth().require_digest();
+        assert!(auth.validate(envelope(UsernameToken::text("alice", "secret")).as_str()).is_none());
+    }
+}
@@ -1,17 +1,31 @@
 use std::collections::HashMap;
-use std::ops::DerefMut;
+use std::convert::TryFrom;
+use std::net::SocketAddr;
 use std::string::ToString;
 use std::sync::{ Arc, Mutex };
 
-use error::SoapError;
-use service;
-use soap::{ Operation, Options, Request };
-use soap::wsdl::Wsdl;
+use futures::future::FutureExt;
+use tokio::sync::oneshot;
+
+use crate::error::SoapError;
+use crate::service::{ self, InFlight };
+use crate::soap::{ Context, Fault, Operation, Options, Packaging, Request, Response, State, Validation };
+use crate::soap::addressing::Delivery;
+use crate::soap::context::OneWay;
+use crate::soap::encoding::TooManyRefs;
+use crate::soap::mtom;
+use crate::soap::middleware::{ Dispatch, Middleware, Next };
+use crate::soap::state::Install;
+use crate::soap::validation;
+use crate::soap::wsdl::Wsdl;
 
 pub struct Service {
         service:    service::Service,
     pub options:    Options,
-    pub operations: Arc<Mutex<HashMap<String, Operation>>>,
+    pub operations: Arc<Mutex<HashMap<String, Arc<Operation>>>>,
+        middleware: Vec<Arc<dyn Middleware>>,
+        state:      Option<Arc<Install>>,
+        delivery:   Option<Delivery>,
 }
 
 impl Service {
@@ -19,65 +33,436 @@ impl Service {
         Service::new_with_opts(host_name, port, Options::default())
     }
 
+    /// A service that is only meant to be `mount`ed on a shared server, so
+    /// it has no address of its own.
+    pub fn new_mountable(opts: Options) -> Service {
+        Service::new_with_opts("localhost", 0, opts)
+    }
+
     pub fn new_with_opts(host_name: &str, port: u16, opts: Options)
         -> Service
     {
-        let service = if opts.bind_addr == "" {
+        let mut service = if opts.bind_addr == "" {
             service::Service::new(host_name, port)
         } else {
             service::Service::new_with_bind(host_name, 
                 opts.bind_addr.as_str(), port)
         };
+
+        if let Some(ref tls) = opts.tls {
+            service.set_tls(tls.clone());
+        }
+
+        service.set_max_body_size(opts.max_body_size);
             
         Service {
             service:    service,
             options:    opts,
             operations: Arc::new(Mutex::new(hashmap!{})),
+            middleware: vec![],
+            state:      None,
+            delivery:   None,
         }
     }
 
     pub fn add_operation(&mut self, operation: Operation) {
         let mut operations = self.operations.lock().unwrap();
-        operations.insert(operation.name.clone(), operation);
+        operations.insert(operation.name.clone(), Arc::new(operation));
+    }
+
+    /// Appends `middleware` to the chain run between envelope parsing and
+    /// operation dispatch.
+    pub fn add_middleware<M: Middleware>(&mut self, middleware: M) {
+        self.middleware.push(Arc::new(middleware));
+    }
+
+    /// Appends `middleware` to the HTTP server behind this service, for
+    /// things that work on the raw envelope, like `security::Encryption`.
+    /// It runs before the envelope is parsed. For `mount`ed services, add
+    /// it to the shared server instead.
+    pub fn add_http_middleware<M: service::Middleware>(&mut self, middleware: M) {
+        self.service.add_middleware(middleware);
+    }
+
+    /// Shares `state` with every call to this service. Operations built
+    /// with `Operation::stateful` receive it by reference; middleware and
+    /// other operations can reach it through `Context::state`.
+    pub fn set_state<T: Send + Sync + 'static>(&mut self, state: T) {
+        let state = State::new(state);
+
+        self.state = Some(Arc::new(move |context| {
+            context.extensions.insert(state.clone());
+        }));
+    }
+
+    /// Accepts WS-Addressing requests whose `ReplyTo` is not anonymous:
+    /// once middleware and validation let them through they are
+    /// acknowledged with `202 Accepted`, and the reply is sent with
+    /// `delivery` when the operation is done. See `Delivery` for which
+    /// callers and addresses are accepted.
+    pub fn set_delivery(&mut self, delivery: Delivery) {
+        self.delivery = Some(delivery);
     }
 
     pub fn get_uri(&self) -> String {
         self.service.get_uri()
     }
 
-    pub fn start(&mut self) -> Result<(), SoapError> {
-        let wsdl       = Wsdl::from(&self).to_string();
-        let operations = self.operations.clone();
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.service.local_addr()
+    }
 
-        let mut not_found = Operation::not_found();
+    pub fn bind(&mut self) -> Result<SocketAddr, SoapError> {
+        self.service.bind()
+    }
 
-        self.service.add_route("/", move |request| {
-            let req = Request::from(request);
+    /// Serves this service at `path` on a shared HTTP server, next to
+    /// whatever else is mounted there. `POST path` takes SOAP calls and
+    /// `GET path?wsdl` returns the WSDL for this service alone.
+    ///
+    /// The server is bound here so the WSDL can advertise its real
+    /// address. `bind_addr`, `tls` and `max_body_size` in this service's
+    /// `Options` are not used; those belong to the shared server.
+    pub fn mount(&self, server: &mut service::Service, path: &str)
+        -> Result<(), SoapError>
+    {
+        server.bind()?;
 
-            let mut lock = operations.lock().unwrap();
-            let     find = lock.get_mut(req.operation.as_str());
+        let mut location = server.get_uri();
+        location.push_str(path.trim_start_matches('/'));
 
-            let mut operation = match find {
-                Some(op) => op,
-                None => &mut not_found,
-            };
+        let wsdl = Wsdl::from(self, location.as_str()).to_string();
+        register(server, path, self.endpoint(), wsdl);
+
+        Ok(())
+    }
+
+    pub async fn start(&mut self) -> Result<service::Handle, SoapError> {
+        // The WSDL advertises the bound address, so bind before building it.
+        self.service.bind()?;
+
+        let location = self.service.get_uri();
+
+        let wsdl     = Wsdl::from(self, location.as_str()).to_string();
+        let endpoint = self.endpoint();
+
+        register(&mut self.service, "/", endpoint, wsdl);
+
+        self.service.start().await
+    }
 
-            let     fun = operation.closure.deref_mut();
-            let mut res = fun(req);
+    fn endpoint(&self) -> Endpoint {
+        Endpoint {
+            operations: self.operations.clone(),
+            middleware: self.middleware.clone(),
+            state:      self.state.clone(),
+            delivery:   self.delivery.clone(),
+            packaging:  if self.options.mtom {
+                Packaging::Mtom
+            } else if self.options.swa {
+                Packaging::Attachments
+            } else {
+                Packaging::Inline
+            },
+            multi_refs: self.options.multi_refs,
+            validation: self.options.validation,
+            namespace:  format!("urn:{}", self.options.namespace),
+        }
+    }
+}
+
+/// Snapshot of what the routes of a started or mounted service need.
+struct Endpoint {
+    operations: Arc<Mutex<HashMap<String, Arc<Operation>>>>,
+    middleware: Vec<Arc<dyn Middleware>>,
+    state:      Option<Arc<Install>>,
+    delivery:   Option<Delivery>,
+    packaging:  Packaging,
+    multi_refs: bool,
+    validation: Validation,
+    namespace:  String,
+}
+
+fn register(server: &mut service::Service, path: &str, endpoint: Endpoint, wsdl: String) {
+    let operations = endpoint.operations;
+    let one_way    = operations.clone();
+    let state      = endpoint.state;
+    let delivery   = endpoint.delivery;
+    let validation = endpoint.validation;
+    let not_found  = Arc::new(Operation::not_found());
+
+    let writer = Writer {
+        packaging:  endpoint.packaging,
+        multi_refs: endpoint.multi_refs,
+        namespace:  endpoint.namespace,
+    };
+
+    let dispatch: Arc<Dispatch> = Arc::new(move |mut req: Request| {
+        let operation = {
+            let lock = operations.lock().unwrap();
+
+            match lock.get(req.operation.as_str()) {
+                Some(op) => op.clone(),
+                None     => not_found.clone(),
+            }
+        };
+
+        req.bind_attachments(&operation.inputs);
+
+        // Unknown operations get their own fault from `not_found`.
+        let violations = if Arc::ptr_eq(&operation, &not_found) {
+            vec![]
+        } else {
+            validation::violations(&operation, &req.arguments, validation)
+        };
+
+        let accept = req.context.extensions.remove::<Accept>();
+
+        async move {
+            let mut res = if !violations.is_empty() {
+                let reason = format!(
+                    "Request does not match the inputs of operation \"{}\"",
+                    operation.name.as_str()
+                );
+
+                let mut response = Response::new();
+                response.fault(Fault::client(reason.as_str(), "", violations.join("; ").as_str()));
+                response
+            } else if let Some(Accept(accept)) = accept {
+                match undeliverable(&req.context) {
+                    Some(fault) => {
+                        let mut response = Response::new();
+                        response.fault(fault);
+                        response
+                    },
+                    None => {
+                        let _ = accept.send(());
+                        (operation.closure)(req).await
+                    },
+                }
+            } else {
+                (operation.closure)(req).await
+            };
 
             res.operation = operation.name.clone();
+            res
+        }.boxed()
+    });
+
+    let middleware = Arc::new(endpoint.middleware);
+
+    server.add_route_with_method("POST", path, move |request| {
+        let dispatch   = dispatch.clone();
+        let middleware = middleware.clone();
+        let state      = state.clone();
+        let delivery   = delivery.clone();
+        let one_way    = one_way.clone();
+        let writer     = writer.clone();
+
+        async move {
+            let mut req = match Request::try_from(request) {
+                Ok(req) => req,
+                Err(e)  => {
+                    let detail = match e {
+                        SoapError::Malformed(reason) => reason,
+                        _                            => String::from("Malformed envelope"),
+                    };
+
+                    let mut res = Response::new();
+                    res.fault(Fault::client("Malformed SOAP request", "", detail.as_str()));
+
+                    return writer.write(res, String::new());
+                },
+            };
+
+            let name = req.operation.clone();
+
+            if req.context.get::<TooManyRefs>().is_some() {
+                let mut res = Response::new();
+                res.fault(Fault::client("Too many multi-reference values to expand", "", ""));
+
+                return writer.write(res, name);
+            }
 
-            let mut response = service::Response::default();
-            response.content = res.to_xml_string();
-            response
-        });
+            if let Some(install) = state {
+                install(&mut req.context);
+            }
+
+            let one_way = {
+                let lock = one_way.lock().unwrap();
+                lock.get(name.as_str()).map(|op| op.is_one_way()).unwrap_or(false)
+            };
+
+            // There is no reply to send back, on this connection or later;
+            // only faults are.
+            if one_way {
+                req.context.insert(OneWay);
+
+                let res = Next::new(&middleware, &*dispatch).run(req).await;
+
+                return match res.get_fault() {
+                    Some(_) => writer.write(res, name),
+                    None    => service::Response::with_status(202),
+                };
+            }
+
+            let deferred = req.context.addressing.clone()
+                .filter(|headers| headers.is_deferred());
+
+            if let (Some(delivery), Some(headers)) = (delivery, deferred) {
+                let (accept, accepted) = oneshot::channel();
+                let in_flight = req.context.get::<InFlight>().cloned();
+
+                req.context.insert(delivery.clone());
+                req.context.insert(Accept(accept));
+
+                // Middleware and validation run before the request is
+                // acknowledged, so their faults go back on this connection.
+                // Only once the operation is about to run does the caller
+                // get its 202, and the rest is left to finish in the
+                // background.
+                let mut call = tokio::spawn(async move {
+                    Next::new(&middleware, &*dispatch).run(req).await
+                });
+
+                tokio::select! {
+                    biased;
+
+                    Ok(()) = accepted => (),
+                    res = &mut call => {
+                        return match res {
+                            Ok(res) => writer.write(res, name),
+                            Err(_)  => service::Response::with_status(500),
+                        };
+                    },
+                }
+
+                tokio::spawn(async move {
+                    // Shutdown waits for the reply to be delivered.
+                    let _in_flight = in_flight;
+
+                    let mut res = match call.await {
+                        Ok(res) => res,
+                        Err(_)  => return,
+                    };
+
+                    let to = match res.get_fault() {
+                        Some(_) => headers.fault_to(),
+                        None    => headers.reply_to(),
+                    };
+
+                    // Nowhere to send it: the caller asked for no reply, or
+                    // for faults on a back-channel that is already closed.
+                    if to.is_anonymous() || to.is_none() {
+                        return;
+                    }
+
+                    writer.prepare(&mut res, name);
+
+                    let _ = delivery.send(to.address.as_str(), res.to_xml_string()).await;
+                });
+
+                return service::Response::with_status(202);
+            }
+
+            let res = Next::new(&middleware, &*dispatch).run(req).await;
+
+            writer.write(res, name)
+        }
+    });
+
+    server.add_route_with_method("GET", path, move |request| {
+        let response = if request.has_query("wsdl") {
+            service::Response {
+                content: String::from_utf8_lossy(wsdl.as_bytes()).into_owned(),
+                ..service::Response::default()
+            }
+        } else {
+            service::Response::with_status(404)
+        };
+
+        async move { response }
+    });
+}
+
+/// Handed to the dispatch of a deferred request, which fires it once the
+/// request passed every check and its operation is about to run.
+struct Accept(oneshot::Sender<()>);
+
+/// Why the reply to a deferred request can't be sent where it asks, if it
+/// can't. Only authenticated callers get replies sent elsewhere, and only
+/// to addresses the `Delivery` allows.
+fn undeliverable(context: &Context) -> Option<Fault> {
+    if context.principal().is_none() {
+        return Some(Fault::client("Replies are only sent to other addresses for authenticated callers", "", ""));
+    }
+
+    let headers  = context.addressing.as_ref()?;
+    let delivery = context.get::<Delivery>()?;
+
+    for to in [headers.reply_to(), headers.fault_to()] {
+        if !to.is_anonymous() && !to.is_none() && !delivery.allows(to.address.as_str()) {
+            let reason = format!("Replies can't be sent to {}", to.address);
+            return Some(Fault::client(reason.as_str(), "", ""));
+        }
+    }
+
+    None
+}
+
+/// How responses of a started or mounted service are written.
+#[derive(Clone)]
+struct Writer {
+    packaging:  Packaging,
+    multi_refs: bool,
+    namespace:  String,
+}
+
+impl Writer {
+    /// Fills in what the operation or middleware left out.
+    fn prepare(&self, res: &mut Response, name: String) {
+        // Middleware answering on its own may not know the name.
+        if res.operation == "" {
+            res.operation = name;
+        }
+
+        res.namespace = self.namespace.clone();
+
+        // The operation may have asked for them on its own.
+        if self.multi_refs {
+            res.set_multi_refs(true);
+        }
+    }
+
+    /// The HTTP response for `res`. Faults are sent with a 500, as SOAP
+    /// 1.1 section 6.2 requires.
+    fn write(&self, mut res: Response, name: String) -> service::Response {
+        self.prepare(&mut res, name);
+
+        // A filter, such as signing, may fail and send a fault instead.
+        let (fault, content, attachments) = match res.to_mime(self.packaging) {
+            Ok((content, attachments)) => (res.get_fault().is_some(), content, attachments),
+            Err(fault)                 => (true, res.faulted(fault).to_xml_string(), vec![]),
+        };
+
+        let mut response = if fault {
+            service::Response::with_status(500)
+        } else {
+            service::Response::default()
+        };
+
+        if !attachments.is_empty() {
+            let root_type = match self.packaging {
+                Packaging::Mtom => mtom::ROOT_TYPE,
+                _               => "text/xml; charset=utf-8",
+            };
+
+            response.header.insert(String::from("content-type"), String::from(root_type));
+            response.attachments = attachments;
+        }
 
-        self.service.add_route("/?wsdl", move |_| {
-            let mut response = service::Response::default();
-            response.content = String::from_utf8_lossy(wsdl.as_bytes()).into_owned();
-            response
-        });
+        response.content = content;
 
-        self.service.start()
+        response
     }
 }
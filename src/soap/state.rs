@@ -0,0 +1,31 @@
+use std::ops::Deref;
+use std::sync::Arc;
+
+use crate::soap::Context;
+
+/// Application state shared by every call to a service, as set with
+/// `Service::set_state`. Cheap to clone.
+pub struct State<T>(Arc<T>);
+
+/// Puts a service's state into the context of each incoming call.
+pub type Install = dyn Fn(&mut Context) + Send + Sync;
+
+impl<T> State<T> {
+    pub fn new(value: T) -> State<T> {
+        State(Arc::new(value))
+    }
+}
+
+impl<T> Clone for State<T> {
+    fn clone(&self) -> State<T> {
+        State(self.0.clone())
+    }
+}
+
+impl<T> Deref for State<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
@@ -0,0 +1,621 @@
+//! Checks requests against the inputs declared by their `Operation`, so
+//! closures can count on the parts they asked for.
+
+use std::collections::HashMap;
+
+use chrono::NaiveDate;
+
+use crate::soap::{ Operation, Part };
+
+/// What to do with request parts an operation does not declare. Missing
+/// parts and values of the wrong type are rejected either way.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Validation {
+    /// Rejects the request.
+    Strict,
+
+    /// Passes them on to the operation.
+    Lax,
+}
+
+/// Everything wrong with `arguments` as inputs of `operation`, in a stable
+/// order. Empty when they are fine.
+pub fn violations(operation: &Operation, arguments: &HashMap<String, Part>, validation: Validation)
+    -> Vec<String>
+{
+    let mut violations = vec![];
+
+    let mut names: Vec<&String> = operation.inputs.keys().collect();
+    names.sort();
+
+    for name in names {
+        let declared = &operation.inputs[name];
+
+        let (declared, optional) = match declared {
+            &Part::Optional(ref part) => (&**part, true),
+            _                         => (declared, false),
+        };
+
+        match arguments.get(name) {
+            None if optional => (),
+            None => violations.push(format!("Missing part \"{}\"", name)),
+
+            Some(&Part::Nil) if optional => (),
+            Some(&Part::Nil) => violations.push(format!("Part \"{}\" is not nillable", name)),
+
+            Some(value) => if !accepts(declared, value) {
+                violations.push(format!(
+                    "Part \"{}\" is not a valid {}", name, declared.xsd_type()));
+            },
+        }
+    }
+
+    if validation == Validation::Strict {
+        let mut unknown: Vec<&String> = arguments.keys()
+            .filter(|name| !operation.inputs.contains_key(name.as_str()))
+            .collect();
+
+        unknown.sort();
+
+        for name in unknown {
+            violations.push(format!("Unexpected part \"{}\"", name));
+        }
+    }
+
+    violations
+}
+
+/// Whether a received `value` fits the `declared` part. Requests are read
+/// as text, so simple values are checked by their lexical form.
+fn accepts(declared: &Part, value: &Part) -> bool {
+    match (declared, value) {
+        (&Part::ComplexType(_, _), &Part::ComplexType(_, _)) => true,
+
+        // Encoded arrays are read as a complex type holding their items.
+        (&Part::Array(ref item), &Part::ComplexType(_, ref content)) => {
+            content.values().all(|items| accepts_items(item, items))
+        },
+        (&Part::Array(ref item), items) => accepts_items(item, items),
+
+        (_, &Part::ComplexType(_, _)) => false,
+
+        // A complex value with no children or attributes.
+        (&Part::ComplexType(_, _), &Part::String(ref text)) => text.trim() == "",
+        (&Part::ComplexType(_, _), _) => false,
+
+        // Attachments, or `cid:` references to them.
+        (&Part::Base64Binary(_), &Part::Binary(_)) |
+        (&Part::Binary(_), &Part::Binary(_)) => true,
+        (&Part::Base64Binary(_), &Part::AnyUri(ref uri)) |
+        (&Part::Binary(_), &Part::AnyUri(ref uri)) => uri.starts_with("cid:"),
+
+        // Their spaces are part of the value.
+        (&Part::NormalizedString(_), &Part::String(ref text)) |
+        (&Part::Token(_), &Part::String(ref text)) => lexical(declared, text),
+
+        (_, &Part::String(ref text)) => lexical(declared, text.trim()),
+        (_, _) => false,
+    }
+}
+
+/// Whether `items`, an array or its only item, fit the `item` type a
+/// declared array has, if any. No text at all is an empty array.
+fn accepts_items(item: &[Part], items: &Part) -> bool {
+    let item = match item.first() {
+        Some(item) => item,
+        None       => return true,
+    };
+
+    let accepts_item = |value: &Part| match (item, value) {
+        (&Part::Optional(_), &Part::Nil) => true,
+        (&Part::Optional(ref item), _)   => accepts(item, value),
+        (_, _)                           => accepts(item, value),
+    };
+
+    match items {
+        &Part::Array(ref values)                     => values.iter().all(accepts_item),
+        &Part::String(ref text) if text.trim() == "" => true,
+        value                                        => accepts_item(value),
+    }
+}
+
+/// Whether `text` is in the lexical space of the XML Schema type of
+/// `declared`.
+fn lexical(declared: &Part, text: &str) -> bool {
+    match declared {
+        &Part::Byte(_)          => integer(text) && text.parse::<i8>().is_ok(),
+        &Part::Short(_)         => integer(text) && text.parse::<i16>().is_ok(),
+        &Part::Int(_)           => integer(text) && text.parse::<i32>().is_ok(),
+        &Part::Long(_)          => integer(text) && text.parse::<i64>().is_ok(),
+        &Part::UnsignedByte(_)  => unsigned(text) && magnitude(text).parse::<u8>().is_ok(),
+        &Part::UnsignedShort(_) => unsigned(text) && magnitude(text).parse::<u16>().is_ok(),
+        &Part::UnsignedInt(_)   => unsigned(text) && magnitude(text).parse::<u32>().is_ok(),
+        &Part::UnsignedLong(_)  => unsigned(text) && magnitude(text).parse::<u64>().is_ok(),
+        &Part::Decimal(_)       => decimal(text),
+
+        // Unbounded, so only their sign is checked.
+        &Part::Integer(_)            => integer(text),
+        &Part::PositiveInteger(_)    => integer(text) && !negative(text) && !zero(text),
+        &Part::NonNegativeInteger(_) => integer(text) && (!negative(text) || zero(text)),
+        &Part::NegativeInteger(_)    => integer(text) && negative(text) && !zero(text),
+        &Part::NonPositiveInteger(_) => integer(text) && (negative(text) || zero(text)),
+
+        &Part::Boolean(_) => match text {
+            "true" | "false" | "1" | "0" => true,
+            _ => false,
+        },
+
+        &Part::Date(_) => timezone(text).map(date).unwrap_or(false),
+        &Part::Time(_) => timezone(text).map(time).unwrap_or(false),
+        &Part::DateTime(_) => {
+            let mut split = timezone(text).unwrap_or("").splitn(2, 'T');
+
+            match (split.next(), split.next()) {
+                (Some(d), Some(t)) => date(d) && time(t),
+                _ => false,
+            }
+        },
+
+        &Part::Base64Binary(_) | &Part::Binary(_) => {
+            let text: String = text.split_whitespace().collect();
+            base64::decode(text).is_ok()
+        },
+
+        &Part::HexBinary(_) => {
+            text.len() % 2 == 0 && text.chars().all(|c| c.is_ascii_hexdigit())
+        },
+
+        &Part::Duration(_) => duration(text),
+
+        &Part::NormalizedString(_) => !text.contains(['\t', '\r', '\n']),
+        &Part::Token(_) => {
+            !text.contains(['\t', '\r', '\n']) && !text.contains("  ")
+                && !text.starts_with(' ') && !text.ends_with(' ')
+        },
+        &Part::Language(_) => language(text),
+        &Part::Name(_)     => name(text),
+        &Part::NmToken(_)  => !text.is_empty() && text.chars().all(name_char),
+        &Part::Id(_) | &Part::IdRef(_) => name(text) && !text.contains(':'),
+        &Part::AnyUri(_)   => uri(text),
+
+        &Part::String(_) => true,
+        &Part::Optional(ref part) => lexical(part, text),
+
+        // Values that are more than text.
+        &Part::ComplexType(_, _) | &Part::Array(_) | &Part::Nil => false,
+    }
+}
+
+/// `-?P(nY)?(nM)?(nD)?(T(nH)?(nM)?(n(.n)?S)?)?`, with at least one field,
+/// and one after `T` if there is one.
+fn duration(text: &str) -> bool {
+    let text = text.strip_prefix('-').unwrap_or(text);
+
+    let text = match text.strip_prefix('P') {
+        Some(text) => text,
+        None       => return false,
+    };
+
+    match text.split_once('T') {
+        Some((date, time)) => fields(date, "YMD") && !time.is_empty() && fields(time, "HMS"),
+        None               => !text.is_empty() && fields(text, "YMD"),
+    }
+}
+
+/// Whether `text` is a run of numbers followed by `designators`, each at
+/// most once and in their order. Only seconds (`S`) have a fraction.
+fn fields(mut text: &str, designators: &str) -> bool {
+    let mut designators = designators.chars();
+
+    while !text.is_empty() {
+        let end = match text.find(|c: char| !c.is_ascii_digit() && c != '.') {
+            Some(end) => end,
+            None      => return false,
+        };
+
+        let (number, rest) = text.split_at(end);
+        let designator     = rest.chars().next().unwrap_or_default();
+
+        // Also skips the designators before it, which can't come later.
+        if !designators.any(|d| d == designator) {
+            return false;
+        }
+
+        let valid = match number.split_once('.') {
+            Some((whole, fraction)) => designator == 'S' && digits(whole) && digits(fraction),
+            None                    => digits(number),
+        };
+
+        if !valid {
+            return false;
+        }
+
+        text = &rest[designator.len_utf8()..];
+    }
+
+    true
+}
+
+/// `[a-zA-Z]{1,8}(-[a-zA-Z0-9]{1,8})*`.
+fn language(text: &str) -> bool {
+    let mut subtags = text.split('-');
+
+    let primary = subtags.next().unwrap_or("");
+
+    (1..=8).contains(&primary.len()) && primary.bytes().all(|b| b.is_ascii_alphabetic())
+        && subtags.all(|tag| (1..=8).contains(&tag.len()) && tag.bytes().all(|b| b.is_ascii_alphanumeric()))
+}
+
+/// An XML `Name`. Without colons, it is an `NCName`.
+fn name(text: &str) -> bool {
+    let mut chars = text.chars();
+
+    match chars.next() {
+        Some(first) => name_start(first) && chars.all(name_char),
+        None        => false,
+    }
+}
+
+/// `NameStartChar` of XML 1.0, fifth edition.
+fn name_start(c: char) -> bool {
+    match c {
+        ':' | 'A'..='Z' | '_' | 'a'..='z' |
+        '\u{C0}'..='\u{D6}' | '\u{D8}'..='\u{F6}' | '\u{F8}'..='\u{2FF}' |
+        '\u{370}'..='\u{37D}' | '\u{37F}'..='\u{1FFF}' | '\u{200C}'..='\u{200D}' |
+        '\u{2070}'..='\u{218F}' | '\u{2C00}'..='\u{2FEF}' | '\u{3001}'..='\u{D7FF}' |
+        '\u{F900}'..='\u{FDCF}' | '\u{FDF0}'..='\u{FFFD}' | '\u{10000}'..='\u{EFFFF}' => true,
+        _ => false,
+    }
+}
+
+/// `NameChar` of XML 1.0, fifth edition.
+fn name_char(c: char) -> bool {
+    name_start(c) || match c {
+        '-' | '.' | '0'..='9' | '\u{B7}' | '\u{300}'..='\u{36F}' | '\u{203F}'..='\u{2040}' => true,
+        _ => false,
+    }
+}
+
+/// A URI reference of RFC 3986: its characters, well-formed `%` escapes,
+/// one fragment at most and a valid scheme, if any. Other characters are
+/// allowed as XML Schema escapes them, so IRIs are URIs too.
+fn uri(text: &str) -> bool {
+    let allowed = |c: char| {
+        !c.is_ascii() || c.is_ascii_alphanumeric() || "-._~:/?#[]@!$&'()*+,;=%".contains(c)
+    };
+
+    let escaped = text.split('%').skip(1)
+        .all(|rest| rest.len() >= 2 && rest.as_bytes()[..2].iter().all(u8::is_ascii_hexdigit));
+
+    let scheme = match text.find(':') {
+        Some(end) if !text[..end].contains(['/', '?', '#']) => {
+            let scheme = &text[..end];
+
+            scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+        },
+        _ => true,
+    };
+
+    text.chars().all(allowed) && escaped && scheme && text.matches('#').count() <= 1
+}
+
+fn digits(text: &str) -> bool {
+    !text.is_empty() && text.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// `text` without its sign.
+fn magnitude(text: &str) -> &str {
+    text.strip_prefix(['+', '-']).unwrap_or(text)
+}
+
+/// `[+-]?[0-9]+`, of any length.
+fn integer(text: &str) -> bool {
+    digits(magnitude(text))
+}
+
+/// An `integer` that isn't negative; `-0` is zero.
+fn unsigned(text: &str) -> bool {
+    integer(text) && (!negative(text) || zero(text))
+}
+
+fn negative(text: &str) -> bool {
+    text.starts_with('-')
+}
+
+fn zero(text: &str) -> bool {
+    magnitude(text).bytes().all(|b| b == b'0')
+}
+
+/// `[+-]?([0-9]+(\.[0-9]*)?|\.[0-9]+)`: no exponent, `INF` or `NaN`.
+fn decimal(text: &str) -> bool {
+    match magnitude(text).split_once('.') {
+        Some((whole, fraction)) => {
+            (whole.is_empty() || digits(whole))
+                && (fraction.is_empty() || digits(fraction))
+                && !(whole.is_empty() && fraction.is_empty())
+        },
+        None => integer(text),
+    }
+}
+
+/// `text` without its time zone, `Z` or `[+-]hh:mm`, if it has none or a
+/// valid one.
+fn timezone(text: &str) -> Option<&str> {
+    if let Some(rest) = text.strip_suffix('Z') {
+        return Some(rest);
+    }
+
+    let split = match text.len().checked_sub(6) {
+        Some(split) if text.is_char_boundary(split) => split,
+        _ => return Some(text),
+    };
+
+    let (rest, zone) = text.split_at(split);
+
+    if !zone.is_ascii() || !zone.starts_with(['+', '-']) || zone.as_bytes()[3] != b':' {
+        return Some(text);
+    }
+
+    match (zone[1..3].parse::<u32>(), zone[4..].parse::<u32>()) {
+        (Ok(hours), Ok(minutes)) if digits(&zone[1..3]) && digits(&zone[4..])
+            && minutes < 60 && (hours < 14 || hours == 14 && minutes == 0) => Some(rest),
+        _ => None,
+    }
+}
+
+/// `-?YYYY-MM-DD`, where years past 9999 have more digits but no leading
+/// zero. There is no year zero.
+fn date(text: &str) -> bool {
+    let (sign, text) = match text.strip_prefix('-') {
+        Some(text) => (-1, text),
+        None       => (1, text),
+    };
+
+    let mut split = text.splitn(3, '-');
+
+    match (split.next(), split.next(), split.next()) {
+        (Some(y), Some(m), Some(d)) => {
+            let shaped = digits(y) && y.len() >= 4 && !(y.len() > 4 && y.starts_with('0'))
+                && digits(m) && m.len() == 2
+                && digits(d) && d.len() == 2;
+
+            match (y.parse::<i32>(), m.parse(), d.parse()) {
+                (Ok(y), Ok(m), Ok(d)) if shaped && y != 0 => NaiveDate::from_ymd_opt(sign * y, m, d).is_some(),
+                _ => false,
+            }
+        },
+        _ => false,
+    }
+}
+
+/// `hh:mm:ss`, with an optional fraction of a second. `24:00:00` is the
+/// end of the day.
+fn time(text: &str) -> bool {
+    let (text, fraction) = match text.split_once('.') {
+        Some((text, fraction)) => (text, Some(fraction)),
+        None                   => (text, None),
+    };
+
+    let shaped = text.len() == 8
+        && fraction.map(digits).unwrap_or(true)
+        && text.bytes().enumerate().all(|(i, b)| match i {
+            2 | 5 => b == b':',
+            _     => b.is_ascii_digit(),
+        });
+
+    let midnight = text == "24:00:00" && fraction.map(|f| f.bytes().all(|b| b == b'0')).unwrap_or(true);
+
+    shaped && (midnight || &text[..2] < "24" && &text[3..5] < "60" && &text[6..8] < "60")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryFrom;
+
+    use bytes::Bytes;
+
+    use crate::service::{ MimePart, Request as ServiceRequest };
+    use crate::soap::{ Request, Response };
+
+    fn string(text: &str) -> Part {
+        Part::String(text.to_string())
+    }
+
+    fn operation(inputs: Vec<(&str, Part)>) -> Operation {
+        let mut operation = Operation::new("call", Operation::closure(|_| Response::new()));
+        operation.inputs = inputs.into_iter().map(|(name, part)| (name.to_string(), part)).collect();
+        operation
+    }
+
+    #[test]
+    fn reports_missing_nil_and_unexpected_parts() {
+        let operation = operation(vec![
+            ("id",    Part::Int(0)),
+            ("name",  Part::String(String::new())),
+            ("note",  Part::optional(Part::String(String::new()))),
+            ("limit", Part::optional(Part::Int(0))),
+        ]);
+
+        let arguments = hashmap!{
+            String::from("name")  => Part::Nil,
+            String::from("limit") => Part::Nil,
+            String::from("extra") => string("x"),
+        };
+
+        assert_eq!(violations(&operation, &arguments, Validation::Strict), vec![
+            String::from("Missing part \"id\""),
+            String::from("Part \"name\" is not nillable"),
+            String::from("Unexpected part \"extra\""),
+        ]);
+
+        assert_eq!(violations(&operation, &arguments, Validation::Lax).len(), 2);
+    }
+
+    #[test]
+    fn numbers_follow_their_lexical_grammar() {
+        for text in ["0", "-0", "+1", "007", "-123456789012345678901234567890"] {
+            assert!(lexical(&Part::Integer(0), text), "{}", text);
+        }
+
+        for text in ["", "+", "1.0", "1e5", " 1", "١"] {
+            assert!(!lexical(&Part::Integer(0), text), "{}", text);
+        }
+
+        for text in ["1", "-1.5", "+.5", "5.", "0012.3400", "123456789012345678901234567890.1"] {
+            assert!(lexical(&Part::Decimal(0.0), text), "{}", text);
+        }
+
+        for text in ["NaN", "INF", "-inf", "1e5", ".", "-", "1.2.3", "1,5"] {
+            assert!(!lexical(&Part::Decimal(0.0), text), "{}", text);
+        }
+
+        assert!(lexical(&Part::PositiveInteger(1), "99999999999999999999999999999999999999999"));
+        assert!(!lexical(&Part::PositiveInteger(1), "-0"));
+        assert!(lexical(&Part::NonPositiveInteger(0), "+0"));
+        assert!(lexical(&Part::NegativeInteger(1), "-99999999999999999999999999999999999999999"));
+        assert!(lexical(&Part::UnsignedByte(0), "-0"));
+        assert!(!lexical(&Part::UnsignedByte(0), "256"));
+        assert!(!lexical(&Part::Byte(0), "128"));
+    }
+
+    #[test]
+    fn dates_and_times_follow_their_lexical_grammar() {
+        for text in ["2020-01-01", "2020-02-29Z", "2020-01-01+05:30", "-0044-03-15", "12020-01-01"] {
+            assert!(lexical(&Part::Date(String::new()), text), "{}", text);
+        }
+
+        for text in ["2020-01-01garbage", "2021-02-29", "2020-1-01", "20-01-01", "02020-01-01", "2020-01-01+15:00",
+                     "0000-01-01", "-0000-01-01"] {
+            assert!(!lexical(&Part::Date(String::new()), text), "{}", text);
+        }
+
+        for text in ["00:00:00", "23:59:59.999", "12:00:00Z", "12:00:00.5-08:00", "24:00:00", "24:00:00.0Z"] {
+            assert!(lexical(&Part::Time(String::new()), text), "{}", text);
+        }
+
+        for text in ["24:00:01", "24:00:00.5", "12:60:00", "12:00", "12:00:00.", "12:00:00garbage", "12:00:00+5:00"] {
+            assert!(!lexical(&Part::Time(String::new()), text), "{}", text);
+        }
+
+        assert!(lexical(&Part::DateTime(String::new()), "2020-01-01T12:00:00.25+01:00"));
+        assert!(lexical(&Part::DateTime(String::new()), "2020-12-31T24:00:00"));
+        assert!(!lexical(&Part::DateTime(String::new()), "2020-01-01T12:00:00garbage"));
+        assert!(!lexical(&Part::DateTime(String::new()), "2020-01-01Z"));
+    }
+
+    #[test]
+    fn durations_follow_their_lexical_grammar() {
+        for text in ["P1Y", "-P1Y2M3DT4H5M6.5S", "PT0S", "P0D", "PT36H", "P1DT1M"] {
+            assert!(lexical(&Part::Duration(String::new()), text), "{}", text);
+        }
+
+        for text in ["", "P", "PT", "P1DT", "1Y", "P1M1Y", "P1Y1Y", "P1.5D", "PT1.S", "P-1D", "P1H", "PT1D"] {
+            assert!(!lexical(&Part::Duration(String::new()), text), "{}", text);
+        }
+    }
+
+    #[test]
+    fn strings_follow_their_lexical_grammar() {
+        let accepted: Vec<(Part, &str)> = vec![
+            (Part::Name(String::new()),     "ns:élément-1.x"),
+            (Part::Name(String::new()),     "_a"),
+            (Part::NmToken(String::new()),  "-1.5"),
+            (Part::Id(String::new()),       "id0"),
+            (Part::IdRef(String::new()),    "_id"),
+            (Part::Language(String::new()), "en"),
+            (Part::Language(String::new()), "zh-Hant-TW"),
+            (Part::Language(String::new()), "x-klingon2"),
+            (Part::Token(String::new()),    "a b c"),
+            (Part::NormalizedString(String::new()), " a  b "),
+            (Part::AnyUri(String::new()),   "http://example.com/a%20b?q=1#top"),
+            (Part::AnyUri(String::new()),   "../relative/päth"),
+            (Part::AnyUri(String::new()),   ""),
+        ];
+
+        for (declared, text) in accepted {
+            assert!(accepts(&declared, &string(text)), "{:?} {}", declared, text);
+        }
+
+        let rejected: Vec<(Part, &str)> = vec![
+            (Part::Name(String::new()),     "1a"),
+            (Part::Name(String::new()),     "a b"),
+            (Part::NmToken(String::new()),  ""),
+            (Part::NmToken(String::new()),  "a,b"),
+            (Part::Id(String::new()),       "ns:id"),
+            (Part::IdRef(String::new()),    "-id"),
+            (Part::Language(String::new()), "englishes"),
+            (Part::Language(String::new()), "e1"),
+            (Part::Language(String::new()), "en-"),
+            (Part::Token(String::new()),    " a"),
+            (Part::Token(String::new()),    "a  b"),
+            (Part::Token(String::new()),    "a\tb"),
+            (Part::NormalizedString(String::new()), "a\nb"),
+            (Part::AnyUri(String::new()),   "http://example.com/a b"),
+            (Part::AnyUri(String::new()),   "http://example.com/%zz"),
+            (Part::AnyUri(String::new()),   "1http://example.com/"),
+            (Part::AnyUri(String::new()),   "a#b#c"),
+        ];
+
+        for (declared, text) in rejected {
+            assert!(!accepts(&declared, &string(text)), "{:?} {:?}", declared, text);
+        }
+    }
+
+    #[test]
+    fn array_items_follow_their_type() {
+        let declared = Part::Array(vec![Part::Int(0)]);
+
+        let encoded = |items: Vec<Part>| Part::ComplexType(hashmap!{}, hashmap!{
+            String::from("item") => Part::Array(items),
+        });
+
+        assert!(accepts(&declared, &Part::Array(vec![string("1"), string("2")])));
+        assert!(accepts(&declared, &encoded(vec![string("1"), string("2")])));
+        assert!(accepts(&declared, &string("1")));
+        assert!(accepts(&declared, &string("")));
+
+        assert!(!accepts(&declared, &Part::Array(vec![string("1"), string("x")])));
+        assert!(!accepts(&declared, &encoded(vec![string("x")])));
+        assert!(!accepts(&declared, &string("x")));
+        assert!(!accepts(&declared, &Part::Array(vec![string("1"), Part::Nil])));
+
+        let nillable = Part::Array(vec![Part::optional(Part::Int(0))]);
+        assert!(accepts(&nillable, &Part::Array(vec![string("1"), Part::Nil])));
+
+        assert!(accepts(&Part::Array(vec![]), &Part::Array(vec![string("x")])));
+    }
+
+    #[test]
+    fn accepts_attachments_for_binary_parts() {
+        assert!(accepts(&Part::Base64Binary(String::new()), &Part::Binary(Bytes::from("x"))));
+        assert!(accepts(&Part::Base64Binary(String::new()), &Part::AnyUri(String::from("cid:x"))));
+        assert!(accepts(&Part::Base64Binary(String::new()), &string("eA==")));
+        assert!(!accepts(&Part::Int(0), &Part::Binary(Bytes::from("x"))));
+    }
+
+    #[test]
+    fn only_body_parts_are_unexpected() {
+        let content = concat!(
+            r#"<SOAP-ENV:Envelope xmlns:SOAP-ENV="http://schemas.xmlsoap.org/soap/envelope/">"#,
+            r#"<SOAP-ENV:Body><call><name>a.png</name></call></SOAP-ENV:Body></SOAP-ENV:Envelope>"#);
+
+        let mut request = ServiceRequest::new(hashmap!{}, content.to_string());
+        request.attachments = vec![
+            MimePart::new("file=1@test", "image/png", "PNG"),
+            MimePart::new("thumbnail=2@test", "image/png", "PNG"),
+        ];
+
+        let operation = operation(vec![
+            ("name", Part::String(String::new())),
+            ("file", Part::Base64Binary(String::new())),
+        ]);
+
+        let mut request = Request::try_from(request).unwrap();
+        request.bind_attachments(&operation.inputs);
+
+        assert!(violations(&operation, &request.arguments, Validation::Strict).is_empty());
+    }
+}
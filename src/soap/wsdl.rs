@@ -1,33 +1,43 @@
+use std::collections::HashMap;
 use std::string::ToString;
 
-extern crate sxd_document;
-use self::sxd_document::Package;
-use self::sxd_document::dom::{ Element, Text };
-use self::sxd_document::writer::format_document;
+use sxd_document::Package;
+use sxd_document::dom::{ Element, Text };
+use sxd_document::writer::format_document;
 
-use soap::{ Operation, Service };
+use crate::soap::{ Operation, Part, Service };
+use crate::soap::addressing;
+use crate::soap::mtom;
+use crate::soap::security::WSU_NS;
+
+/// The part of a wrapped message that refers to its wrapper element.
+const WRAPPER_PART: &str = "parameters";
 
 pub struct Wsdl<'a> {
-    service: &'a Service,
-    package: Package,
+    service:  &'a Service,
+    location: String,
+    package:  Package,
 }
 
 impl<'a> Wsdl<'a> {
-    pub fn from(service: &'a Service) -> Wsdl {
+    /// Describes `service` as reachable at `location`, which becomes the
+    /// `soap:address` of its port.
+    pub fn from(service: &'a Service, location: &str) -> Wsdl<'a> {
         let wsdl = Wsdl {
-            service: service,
-            package: Package::new(),
+            service:  service,
+            location: location.to_string(),
+            package:  Package::new(),
         };
 
         wsdl.generate();
         wsdl
     }
 
-    fn create_element(&self, tag_name: &str) -> Element {
+    fn create_element(&self, tag_name: &str) -> Element<'_> {
         self.package.as_document().create_element(tag_name)
     }
 
-    fn create_text(&self, text: &str) -> Text {
+    fn create_text(&self, text: &str) -> Text<'_> {
         self.package.as_document().create_text(text)
     }
 
@@ -41,6 +51,7 @@ impl<'a> Wsdl<'a> {
         definitions.set_attribute_value("xmlns:xsi",       "http://www.w3.org/2001/XMLSchema-instance");
         definitions.set_attribute_value("xmlns:SOAP-ENV",  "http://schemas.xmlsoap.org/soap/envelope/");
         definitions.set_attribute_value("xmlns:SOAP-ENC",  "http://schemas.xmlsoap.org/soap/encoding/");
+        definitions.set_attribute_value("xmlns:wsam",      addressing::WSAM_NS);
 
         {
             let mut urn = String::from("urn:");
@@ -62,15 +73,31 @@ impl<'a> Wsdl<'a> {
 
             for (_, operation) in operations.iter() {
                 definitions.append_child(
-                    self.register_inputs(&operation)
+                    self.register_inputs(operation)
                 );
 
-                definitions.append_child(
-                    self.register_outputs(&operation)
-                );
+                if !operation.is_one_way() {
+                    definitions.append_child(
+                        self.register_outputs(operation)
+                    );
+                }
             }
         }
 
+        if self.uses_swa() {
+            definitions.set_attribute_value("xmlns:mime", "http://schemas.xmlsoap.org/wsdl/mime/");
+        }
+
+        if self.service.options.mtom {
+            definitions.set_attribute_value("xmlns:wsp",   mtom::WSP_NS);
+            definitions.set_attribute_value("xmlns:wsoma", mtom::WSOMA_NS);
+            definitions.set_attribute_value("xmlns:wsu",   WSU_NS);
+
+            definitions.append_child(
+                self.register_mtom_policy()
+            );
+        }
+
         definitions.append_child(
             self.register_ports()
         );
@@ -86,12 +113,16 @@ impl<'a> Wsdl<'a> {
         self.package.as_document().root().append_child(definitions);
     }
 
-    fn import_schemas(&self, schemas: Vec<&str>) -> Element {
+    fn import_schemas(&self, schemas: Vec<&str>) -> Element<'_> {
         let types  = self.create_element("types");
 
+        // The wrapper elements are those the operations read and write, in
+        // the namespace of the definitions.
+        let mut urn = String::from("urn:");
+        urn.push_str(self.service.options.namespace.as_str());
+
         let schema = self.create_element("xsd:schema");
-        schema.set_attribute_value("targetNamespace",
-            self.service.options.namespace.as_str());
+        schema.set_attribute_value("targetNamespace", urn.as_str());
 
         for sch in schemas.iter() {
             let import = self.create_element("xsd:import");
@@ -100,48 +131,136 @@ impl<'a> Wsdl<'a> {
             schema.append_child(import);
         }
 
+        for wrapper in self.register_wrappers() {
+            schema.append_child(wrapper);
+        }
+
         types.append_child(schema);
 
         types
     }
 
-    fn register_inputs(&self, operation: &Operation) -> Element {
+    /// The elements wrapping the messages of `is_wrapped` operations, with
+    /// `minOccurs="0"` and `nillable="true"` on their optional parts.
+    fn register_wrappers(&self) -> Vec<Element<'_>> {
+        let operations = self.service.operations.lock().unwrap();
+        let mut wrappers = vec![];
+
+        let mut names: Vec<&String> = operations.keys().collect();
+        names.sort();
+
+        for name in names {
+            let operation = &operations[name];
+
+            if !is_wrapped(operation) {
+                continue;
+            }
+
+            let mut messages = vec![(operation.name.clone(), &operation.inputs)];
+
+            if !operation.is_one_way() {
+                messages.push((format!("{}Response", operation.name), &operation.outputs));
+            }
+
+            for (name, parts) in messages {
+                let sequence = self.create_element("xsd:sequence");
+
+                let mut names: Vec<&String> = parts.keys()
+                    .filter(|name| !self.in_mime(&parts[*name]))
+                    .collect();
+
+                names.sort();
+
+                for name in names {
+                    let part    = &parts[name];
+                    let element = self.create_element("xsd:element");
+                    element.set_attribute_value("name", name.as_str());
+                    element.set_attribute_value("type", part.xsd_type().as_str());
+
+                    if part.is_optional() {
+                        element.set_attribute_value("minOccurs", "0");
+                        element.set_attribute_value("nillable", "true");
+                    }
+
+                    sequence.append_child(element);
+                }
+
+                let complex = self.create_element("xsd:complexType");
+                complex.append_child(sequence);
+
+                let wrapper = self.create_element("xsd:element");
+                wrapper.set_attribute_value("name", name.as_str());
+                wrapper.append_child(complex);
+
+                wrappers.push(wrapper);
+            }
+        }
+
+        wrappers
+    }
+
+    fn register_inputs(&self, operation: &Operation) -> Element<'_> {
         let mut name = operation.name.clone();
         name.push_str("Request");
 
         let message = self.create_element("message");
         message.set_attribute_value("name", name.as_str());
-        
-        for (name, arg) in operation.inputs.iter() {
-            let part = self.create_element("part");
-            part.set_attribute_value("name", name);
-            part.set_attribute_value("type", arg.xsd_type().as_str());
 
-            message.append_child(part);
-        }
+        let wrapper = format!("tns:{}", operation.name);
+        self.register_parts(message, &operation.inputs, is_wrapped(operation), wrapper.as_str());
 
         message
     }
 
-    fn register_outputs(&self, operation: &Operation) -> Element {
+    fn register_outputs(&self, operation: &Operation) -> Element<'_> {
         let mut name = operation.name.clone();
         name.push_str("Response");
 
         let message = self.create_element("message");
         message.set_attribute_value("name", name.as_str());
-        
-        for (name, arg) in operation.outputs.iter() {
+
+        let wrapper = format!("tns:{}Response", operation.name);
+        self.register_parts(message, &operation.outputs, is_wrapped(operation), wrapper.as_str());
+
+        message
+    }
+
+    /// Adds a typed part per argument or, for a `wrapped` message, a
+    /// `parameters` part referring to the `wrapper` element. Binary parts
+    /// bound to MIME stay parts of their own either way.
+    fn register_parts(&self, message: Element, parts: &HashMap<String, Part>, wrapped: bool, wrapper: &str) {
+        if wrapped {
+            let part = self.create_element("part");
+            part.set_attribute_value("name", WRAPPER_PART);
+            part.set_attribute_value("element", wrapper);
+
+            message.append_child(part);
+        }
+
+        for (name, arg) in parts.iter() {
+            if wrapped && !self.in_mime(arg) {
+                continue;
+            }
+
             let part = self.create_element("part");
             part.set_attribute_value("name", name);
             part.set_attribute_value("type", arg.xsd_type().as_str());
 
             message.append_child(part);
         }
+    }
 
-        message
+    fn register_mtom_policy(&self) -> Element<'_> {
+        let policy = self.create_element("wsp:Policy");
+        policy.set_attribute_value("wsu:Id", "MtomPolicy");
+
+        let assertion = self.create_element("wsoma:OptimizedMimeSerialization");
+        policy.append_child(assertion);
+
+        policy
     }
 
-    fn register_ports(&self) -> Element {
+    fn register_ports(&self) -> Element<'_> {
         let mut port_name = self.service.options.service_name.clone();
         port_name.push_str("PortType");
 
@@ -161,8 +280,12 @@ impl<'a> Wsdl<'a> {
             input_str.push_str(op.name.as_str());
             input_str.push_str("Request");
 
+            let options = &self.service.options;
+
             let input = self.create_element("input");
             input.set_attribute_value("message", input_str.as_str());
+            input.set_attribute_value("wsam:Action",
+                addressing::action(options, op.name.as_str(), "Request").as_str());
 
             let mut output_str = String::from("tns:");
             output_str.push_str(op.name.as_str());
@@ -170,17 +293,23 @@ impl<'a> Wsdl<'a> {
 
             let output = self.create_element("output");
             output.set_attribute_value("message", output_str.as_str());
+            output.set_attribute_value("wsam:Action",
+                addressing::action(options, op.name.as_str(), "Response").as_str());
 
             operation.append_child(doc);
             operation.append_child(input);
-            operation.append_child(output);
+
+            if !op.is_one_way() {
+                operation.append_child(output);
+            }
+
             port.append_child(operation);
         }
 
         port
     }
 
-    fn register_bindings(&self) -> Element {
+    fn register_bindings(&self) -> Element<'_> {
         let mut port_name = String::from("tns:");
         port_name.push_str(self.service.options.service_name.as_str());
         port_name.push_str("PortType");
@@ -198,6 +327,12 @@ impl<'a> Wsdl<'a> {
         bind.set_attribute_value("name", bind_name.as_str());
         bind.set_attribute_value("type", port_name.as_str());
 
+        if self.service.options.mtom {
+            let reference = self.create_element("wsp:PolicyReference");
+            reference.set_attribute_value("URI", "#MtomPolicy");
+            bind.append_child(reference);
+        }
+
         let soap_bind = self.create_element("soap:binding");
         soap_bind.set_attribute_value("style", "rpc");
         soap_bind.set_attribute_value("transport", "http://schemas.xmlsoap.org/soap/http");
@@ -218,24 +353,24 @@ impl<'a> Wsdl<'a> {
             let operation = self.create_element("operation");
             operation.set_attribute_value("name", op.name.as_str());
 
+            let wrapped = is_wrapped(op);
+
             let soap_op = self.create_element("soap:operation");
             soap_op.set_attribute_value("soapAction", tns_anchor.as_str());
-            soap_op.set_attribute_value("style", "rpc");
-
-            let soap_body = self.create_element("soap:body");
-            soap_body.set_attribute_value("use", "encoded");
-            soap_body.set_attribute_value("namespace", tns.as_str());
-            soap_body.set_attribute_value("encodingStyle", "http://schemas.xmlsoap.org/soap/encoding/");
+            soap_op.set_attribute_value("style", if wrapped { "document" } else { "rpc" });
 
             let soap_input = self.create_element("input");
-            soap_input.append_child(soap_body.clone());
+            soap_input.append_child(self.bind_message(tns.as_str(), &op.inputs, wrapped));
 
             let soap_output = self.create_element("output");
-            soap_output.append_child(soap_body);
+            soap_output.append_child(self.bind_message(tns.as_str(), &op.outputs, wrapped));
 
             operation.append_child(soap_op);
             operation.append_child(soap_input);
-            operation.append_child(soap_output);
+
+            if !op.is_one_way() {
+                operation.append_child(soap_output);
+            }
 
             bind.append_child(operation);
         }
@@ -243,7 +378,78 @@ impl<'a> Wsdl<'a> {
         bind
     }
 
-    fn register_service(&self) -> Element {
+    /// `soap:body` for a message or, with SwA, a `mime:multipartRelated`
+    /// that also binds its binary parts to MIME parts of their own.
+    /// `wrapped` messages are literal, as their wrapper element is declared.
+    fn bind_message(&self, tns: &str, parts: &HashMap<String, Part>, wrapped: bool) -> Element<'_> {
+        let binary: Vec<&String> = parts.iter()
+            .filter(|&(_, part)| self.in_mime(part))
+            .map(|(name, _)| name)
+            .collect();
+
+        let soap_body = self.create_element("soap:body");
+
+        if wrapped {
+            soap_body.set_attribute_value("use", "literal");
+        } else {
+            soap_body.set_attribute_value("use", "encoded");
+            soap_body.set_attribute_value("namespace", tns);
+            soap_body.set_attribute_value("encodingStyle", "http://schemas.xmlsoap.org/soap/encoding/");
+        }
+
+        if binary.is_empty() {
+            return soap_body;
+        }
+
+        let mut in_body: Vec<&String> = if wrapped {
+            vec![]
+        } else {
+            parts.keys().filter(|name| !binary.contains(name)).collect()
+        };
+
+        in_body.sort();
+
+        let mut in_body: Vec<&str> = in_body.iter().map(|name| name.as_str()).collect();
+
+        if wrapped {
+            in_body.push(WRAPPER_PART);
+        }
+
+        soap_body.set_attribute_value("parts", in_body.join(" ").as_str());
+
+        let multipart = self.create_element("mime:multipartRelated");
+
+        let root = self.create_element("mime:part");
+        root.append_child(soap_body);
+        multipart.append_child(root);
+
+        let mut binary = binary;
+        binary.sort();
+
+        for name in binary {
+            let content = self.create_element("mime:content");
+            content.set_attribute_value("part", name.as_str());
+            content.set_attribute_value("type", "application/octet-stream");
+
+            let part = self.create_element("mime:part");
+            part.append_child(content);
+            multipart.append_child(part);
+        }
+
+        multipart
+    }
+
+    /// MTOM takes over binary parts when both are enabled.
+    fn uses_swa(&self) -> bool {
+        self.service.options.swa && !self.service.options.mtom
+    }
+
+    /// Whether `part` is sent as a MIME part rather than in the body.
+    fn in_mime(&self, part: &Part) -> bool {
+        self.uses_swa() && part.is_binary()
+    }
+
+    fn register_service(&self) -> Element<'_> {
         let service_name = &self.service.options.service_name;
 
         let mut port_name = service_name.clone();
@@ -262,7 +468,7 @@ impl<'a> Wsdl<'a> {
         port.set_attribute_value("binding", bind_name.as_str());
 
         let address = self.create_element("soap:address");
-        address.set_attribute_value("location", self.service.get_uri().as_str());
+        address.set_attribute_value("location", self.location.as_str());
 
         port.append_child(address);
         srv.append_child(port);
@@ -271,6 +477,7 @@ impl<'a> Wsdl<'a> {
     }
 }
 
+#[allow(clippy::to_string_trait_impl)]
 impl<'a> ToString for Wsdl<'a> {
     fn to_string(&self) -> String {
         let mut buffer = Vec::new();
@@ -279,3 +486,102 @@ impl<'a> ToString for Wsdl<'a> {
     }
 }
 
+/// Parts of rpc/encoded messages can't be optional or nillable, so
+/// operations with optional parts are bound document/literal instead, with
+/// each message wrapped in an element named like the rpc one.
+fn is_wrapped(operation: &Operation) -> bool {
+    operation.inputs.values().any(|part| part.is_optional())
+        || !operation.is_one_way() && operation.outputs.values().any(|part| part.is_optional())
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use sxd_document::parser;
+
+    use crate::soap::{ Options, Response };
+
+    fn children<'d>(element: Element<'d>, local: &str) -> Vec<Element<'d>> {
+        element.children().into_iter()
+            .filter_map(|child| child.element())
+            .filter(|child| child.name().local_part() == local)
+            .collect()
+    }
+
+    fn named<'d>(element: Element<'d>, local: &str, name: &str) -> Element<'d> {
+        children(element, local).into_iter()
+            .find(|child| child.attribute_value("name") == Some(name))
+            .unwrap()
+    }
+
+    #[test]
+    fn optional_parts_are_declared_nillable() {
+        let options = Options {
+            bind_addr: String::from("127.0.0.1"),
+            namespace: String::from("test"),
+            ..Options::default()
+        };
+
+        let mut service = Service::new_with_opts("127.0.0.1", 0, options);
+
+        let mut lookup = Operation::new("lookup", Operation::closure(|_| Response::new()));
+        lookup.inputs = hashmap!{
+            String::from("name")  => Part::String(String::new()),
+            String::from("limit") => Part::optional(Part::Int(0)),
+        };
+
+        let mut echo = Operation::new("echo", Operation::closure(|_| Response::new()));
+        echo.inputs = hashmap!{ String::from("text") => Part::String(String::new()) };
+
+        service.add_operation(lookup);
+        service.add_operation(echo);
+
+        let wsdl        = Wsdl::from(&service, "http://localhost/").to_string();
+        let package     = parser::parse(wsdl.as_str()).unwrap();
+        let definitions = package.as_document().root().children()[0].element().unwrap();
+
+        // The message refers to its wrapper, which is declared in the
+        // namespace the reference resolves to.
+        let message = named(definitions, "message", "lookupRequest");
+        let parts   = children(message, "part");
+
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].attribute_value("element"), Some("tns:lookup"));
+
+        let schema = children(children(definitions, "types")[0], "schema")[0];
+        assert_eq!(schema.attribute_value("targetNamespace"), parts[0].namespace_uri_for_prefix("tns"));
+
+        let wrapper  = named(schema, "element", "lookup");
+        let sequence = children(children(wrapper, "complexType")[0], "sequence")[0];
+
+        let limit = named(sequence, "element", "limit");
+        assert_eq!(limit.attribute_value("type"), Some("xsd:int"));
+        assert_eq!(limit.attribute_value("minOccurs"), Some("0"));
+        assert_eq!(limit.attribute_value("nillable"), Some("true"));
+
+        let name = named(sequence, "element", "name");
+        assert_eq!(name.attribute_value("type"), Some("xsd:string"));
+        assert_eq!(name.attribute_value("minOccurs"), None);
+
+        assert_eq!(children(named(definitions, "message", "lookupResponse"), "part")[0]
+            .attribute_value("element"), Some("tns:lookupResponse"));
+        named(schema, "element", "lookupResponse");
+
+        // Wrapped messages are literal; the others stay rpc/encoded.
+        let binding = children(definitions, "binding")[0];
+
+        for (operation, style, body_use) in [("lookup", "document", "literal"), ("echo", "rpc", "encoded")] {
+            let operation = named(binding, "operation", operation);
+
+            assert_eq!(children(operation, "operation")[0].attribute_value("style"), Some(style));
+            assert_eq!(children(children(operation, "input")[0], "body")[0]
+                .attribute_value("use"), Some(body_use));
+        }
+
+        let echo = children(named(definitions, "message", "echoRequest"), "part");
+        assert_eq!(echo[0].attribute_value("type"), Some("xsd:string"));
+        assert!(children(schema, "element").iter().all(|e| e.attribute_value("name") != Some("echo")));
+    }
+}
@@ -0,0 +1,184 @@
+extern crate webservices;
+
+use std::time::Duration;
+
+use hyper::{ Body, Client as HttpClient, Method, Request as HttpRequest };
+use tokio::sync::mpsc;
+
+use webservices::service::{ self, Handle };
+use webservices::soap::{ Client, Operation, Options, Part, Response, Service };
+use webservices::soap::addressing::{ self, Addressing, Delivery, WSA_NS };
+use webservices::soap::security::{ UsernameToken, UsernameTokenAuth };
+
+fn options() -> Options {
+    Options {
+        bind_addr: String::from("127.0.0.1"),
+        namespace: String::from("test"),
+        ..Options::default()
+    }
+}
+
+/// A server collecting the replies POSTed to `/replies`.
+async fn receiver() -> (Handle, String, mpsc::UnboundedReceiver<String>) {
+    let (sender, received) = mpsc::unbounded_channel();
+
+    let mut server = service::Service::new_with_bind("127.0.0.1", "127.0.0.1", 0);
+
+    server.add_route("/replies", move |request| {
+        let _ = sender.send(request.content);
+        async move { service::Response::default() }
+    });
+
+    let handle  = server.start().await.unwrap();
+    let replies = format!("http://{}/replies", handle.local_addr());
+
+    (handle, replies, received)
+}
+
+/// A service whose `greet` takes `delay` to answer, delivering replies to
+/// `allowed`. Callers authenticate with a UsernameToken when `auth` is set.
+async fn start(allowed: &str, auth: bool, delay: Duration) -> (Handle, String) {
+    let mut service = Service::new_with_opts("127.0.0.1", 0, options());
+
+    let mut greet = Operation::new("greet", Operation::async_closure(move |request| async move {
+        tokio::time::sleep(delay).await;
+
+        let mut response = Response::new();
+        response.responses.insert(String::from("greeting"),
+            Part::String(format!("hello {}", request.arguments["name"].text())));
+        response
+    }));
+
+    greet.inputs  = vec![(String::from("name"), Part::String(String::new()))].into_iter().collect();
+    greet.outputs = vec![(String::from("greeting"), Part::String(String::new()))].into_iter().collect();
+
+    service.add_operation(greet);
+
+    if auth {
+        service.add_middleware(UsernameTokenAuth::new(|user: &str| match user {
+            "alice" => Some(String::from("secret")),
+            _       => None,
+        }));
+    }
+
+    service.add_middleware(Addressing::new(&options()));
+    service.set_delivery(Delivery::new().allow(allowed));
+
+    let handle = service.start().await.unwrap();
+    let uri    = service.get_uri();
+
+    (handle, uri)
+}
+
+/// POSTs a `greet` call whose replies are to go to `reply_to`, and returns
+/// the status and body of the immediate answer.
+async fn greet(uri: &str, reply_to: &str, token: Option<UsernameToken>) -> (u16, String) {
+    let mut client = Client::new(uri, "test");
+
+    if let Some(token) = token {
+        client.set_username_token(token);
+    }
+
+    let arguments = vec![(String::from("name"), Part::String(String::from("bob")))].into_iter().collect();
+    let envelope  = client.envelope("greet", &arguments);
+
+    let headers = format!(concat!(
+        "<SOAP-ENV:Header xmlns:wsa=\"{}\">",
+        "<wsa:Action>{}</wsa:Action>",
+        "<wsa:MessageID>{}</wsa:MessageID>",
+        "<wsa:ReplyTo><wsa:Address>{}</wsa:Address></wsa:ReplyTo>"),
+        WSA_NS, addressing::action(&options(), "greet", "Request"), addressing::message_id(), reply_to);
+
+    let envelope = if envelope.contains("<SOAP-ENV:Header>") {
+        envelope.replace("<SOAP-ENV:Header>", headers.as_str())
+    } else {
+        envelope.replace("<SOAP-ENV:Body>", format!("{}</SOAP-ENV:Header><SOAP-ENV:Body>", headers).as_str())
+    };
+
+    let request = HttpRequest::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header("Content-Type", "text/xml; charset=utf-8")
+        .body(Body::from(envelope))
+        .unwrap();
+
+    let response = HttpClient::new().request(request).await.unwrap();
+    let status   = response.status().as_u16();
+    let body     = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn delivers_replies_to_allowed_addresses() {
+    let (receiving, replies, mut received) = receiver().await;
+    let (handle, uri) = start(replies.as_str(), true, Duration::from_millis(0)).await;
+
+    let (status, _) = greet(uri.as_str(), replies.as_str(), Some(UsernameToken::digest("alice", "secret"))).await;
+    assert_eq!(status, 202);
+
+    let reply = tokio::time::timeout(Duration::from_secs(5), received.recv()).await.unwrap().unwrap();
+    assert!(reply.contains("hello bob"));
+    assert!(reply.contains("RelatesTo"));
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+    receiving.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn answers_rejected_requests_inline() {
+    let (receiving, replies, mut received) = receiver().await;
+
+    // Bad credentials: the fault comes back before anything runs.
+    let (handle, uri) = start(replies.as_str(), true, Duration::from_millis(0)).await;
+    let (status, body) = greet(uri.as_str(), replies.as_str(), Some(UsernameToken::text("alice", "wrong"))).await;
+
+    assert_eq!(status, 500);
+    assert!(body.contains("FailedAuthentication"));
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+
+    // An address the service doesn't deliver to.
+    let (handle, uri) = start(replies.as_str(), true, Duration::from_millis(0)).await;
+    let elsewhere = format!("{}-elsewhere", replies);
+    let (status, body) = greet(uri.as_str(), elsewhere.as_str(), Some(UsernameToken::digest("alice", "secret"))).await;
+
+    assert_eq!(status, 500);
+    assert!(body.contains("InvalidAddressingHeader"));
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+
+    // An https address, which replies could never reach.
+    let secure = replies.replacen("http://", "https://", 1);
+    let (handle, uri) = start(secure.as_str(), true, Duration::from_millis(0)).await;
+    let (status, body) = greet(uri.as_str(), secure.as_str(), Some(UsernameToken::digest("alice", "secret"))).await;
+
+    assert_eq!(status, 500);
+    assert!(body.contains("InvalidAddressingHeader"));
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+
+    // Nobody to vouch for the caller.
+    let (handle, uri) = start(replies.as_str(), false, Duration::from_millis(0)).await;
+    let (status, body) = greet(uri.as_str(), replies.as_str(), None).await;
+
+    assert_eq!(status, 500);
+    assert!(body.contains("authenticated callers"));
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+
+    assert!(received.try_recv().is_err());
+    receiving.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn shutdown_waits_for_pending_replies() {
+    let (receiving, replies, mut received) = receiver().await;
+    let (handle, uri) = start(replies.as_str(), true, Duration::from_millis(300)).await;
+
+    let (status, _) = greet(uri.as_str(), replies.as_str(), Some(UsernameToken::digest("alice", "secret"))).await;
+    assert_eq!(status, 202);
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+
+    let reply = received.try_recv().expect("the reply was delivered before shutting down");
+    assert!(reply.contains("hello bob"));
+
+    receiving.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
@@ -0,0 +1,290 @@
+extern crate webservices;
+
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use hyper::{ Body, Client as HttpClient, Method, Request as HttpRequest };
+
+use webservices::service::{ self, Handle };
+use webservices::soap::{ Client, Operation, Options, Part, Response, Service };
+use webservices::soap::security::{ Signature, UsernameToken, UsernameTokenAuth };
+
+const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/cert.pem");
+const KEY:  &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/key.pem");
+
+fn string(value: &str) -> Part {
+    Part::String(value.to_string())
+}
+
+async fn start() -> (Handle, String) {
+    let options = Options {
+        bind_addr: String::from("127.0.0.1"),
+        namespace: String::from("test"),
+        ..Options::default()
+    };
+
+    let mut service = Service::new_with_opts("127.0.0.1", 0, options);
+
+    let mut greet = Operation::new("greet", Operation::closure(|request| {
+        let name = request.arguments["name"].text();
+        let user = request.context.principal().map(|p| p.name.clone()).unwrap_or_default();
+
+        let mut response = Response::new();
+        response.responses.insert(String::from("greeting"), string(format!("hello {} from {}", name, user).as_str()));
+        response
+    }));
+
+    greet.doc     = String::from("Greets someone.");
+    greet.inputs  = hashmap(vec![("name", string(""))]);
+    greet.outputs = hashmap(vec![("greeting", string(""))]);
+
+    service.add_operation(greet);
+
+    let mut notify = Operation::new("notify", Operation::closure(|_| Response::new())).one_way();
+    notify.inputs = hashmap(vec![("message", string(""))]);
+
+    service.add_operation(notify);
+
+    service.add_middleware(UsernameTokenAuth::new(|user: &str| match user {
+        "alice" => Some(String::from("secret")),
+        _       => None,
+    }));
+
+    let handle = service.start().await.unwrap();
+    let uri    = service.get_uri();
+
+    (handle, uri)
+}
+
+fn hashmap(entries: Vec<(&str, Part)>) -> HashMap<String, Part> {
+    entries.into_iter().map(|(k, v)| (k.to_string(), v)).collect()
+}
+
+#[tokio::test]
+async fn client_calls_service() {
+    let (handle, uri) = start().await;
+
+    let mut client = Client::new(uri.as_str(), "test");
+    client.set_username_token(UsernameToken::digest("alice", "secret"));
+
+    let response = client.call("greet", hashmap(vec![("name", string("bob"))])).await.unwrap();
+
+    assert!(response.get_fault().is_none());
+    assert_eq!(response.operation, "greet");
+    assert_eq!(response.responses["greeting"], string("hello bob from alice"));
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn client_gets_faults_back() {
+    let (handle, uri) = start().await;
+
+    let mut client = Client::new(uri.as_str(), "test");
+    client.set_username_token(UsernameToken::text("alice", "wrong"));
+
+    let response = client.call("greet", hashmap(vec![("name", string("bob"))])).await.unwrap();
+    let fault    = response.get_fault().expect("a fault");
+
+    assert!(fault.code().ends_with("FailedAuthentication"));
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn faults_are_sent_with_500() {
+    let (handle, uri) = start().await;
+
+    let mut client = Client::new(uri.as_str(), "test");
+    client.set_username_token(UsernameToken::digest("alice", "secret"));
+
+    let post = |envelope: String| {
+        let request = HttpRequest::builder()
+            .method(Method::POST)
+            .uri(uri.as_str())
+            .header("Content-Type", "text/xml; charset=utf-8")
+            .body(Body::from(envelope))
+            .unwrap();
+
+        HttpClient::new().request(request)
+    };
+
+    let ok = post(client.envelope("greet", &hashmap(vec![("name", string("bob"))]))).await.unwrap();
+    assert_eq!(ok.status().as_u16(), 200);
+
+    // Invalid arguments, and an operation that doesn't exist.
+    let invalid = post(client.envelope("greet", &hashmap(vec![]))).await.unwrap();
+    assert_eq!(invalid.status().as_u16(), 500);
+
+    let unknown = post(client.envelope("missing", &hashmap(vec![]))).await.unwrap();
+    assert_eq!(unknown.status().as_u16(), 500);
+
+    // Envelopes that can't be read at all are the client's fault too.
+    for (envelope, detail) in [("", "Not well-formed XML"), ("<Envelope/>", "Missing SOAP Body")] {
+        let malformed = post(envelope.to_string()).await.unwrap();
+        assert_eq!(malformed.status().as_u16(), 500);
+
+        let body = hyper::body::to_bytes(malformed.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains(format!(">{}<", detail).as_str()), "{}", body);
+
+        let read = Response::from_xml(body).unwrap();
+        assert_eq!(read.get_fault().map(|fault| fault.code()), Some(String::from("Client")));
+    }
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn one_way_calls_are_accepted_unless_they_fault() {
+    let (handle, uri) = start().await;
+
+    let mut client = Client::new(uri.as_str(), "test");
+    client.set_username_token(UsernameToken::digest("alice", "secret"));
+
+    assert!(client.send("notify", hashmap(vec![("message", string("hi"))])).await.is_ok());
+
+    // Faults are not swallowed into a 202: the caller gets the fault.
+    let response = client.call("notify", hashmap(vec![])).await.unwrap();
+    assert_eq!(response.get_fault().map(|fault| fault.code()), Some(String::from("Client")));
+
+    client.set_username_token(UsernameToken::text("alice", "wrong"));
+    assert!(client.send("notify", hashmap(vec![("message", string("hi"))])).await.is_err());
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+/// A mountable service in namespace `name` whose only operation, also
+/// called `name`, answers with `name`.
+fn named(name: &'static str) -> Service {
+    let options = Options {
+        namespace: name.to_string(),
+        ..Options::default()
+    };
+
+    let mut service   = Service::new_mountable(options);
+    let mut operation = Operation::new(name, Operation::closure(move |_| {
+        let mut response = Response::new();
+        response.responses.insert(String::from("name"), string(name));
+        response
+    }));
+
+    operation.outputs = hashmap(vec![("name", string(""))]);
+    service.add_operation(operation);
+
+    service
+}
+
+#[tokio::test]
+async fn mounted_services_answer_on_their_own_paths() {
+    let mut server = service::Service::new_with_bind("127.0.0.1", "127.0.0.1", 0);
+
+    named("first").mount(&mut server, "/first").unwrap();
+    named("second").mount(&mut server, "/second").unwrap();
+
+    let handle = server.start().await.unwrap();
+    let base   = format!("http://{}", handle.local_addr());
+
+    for (name, other) in [("first", "second"), ("second", "first")] {
+        let uri      = format!("{}/{}", base, name);
+        let response = Client::new(uri.as_str(), name).call(name, hashmap(vec![])).await.unwrap();
+
+        assert!(response.get_fault().is_none());
+        assert_eq!(response.responses["name"], string(name));
+
+        let wsdl = HttpClient::new().get(format!("{}?wsdl", uri).parse().unwrap()).await.unwrap();
+        let wsdl = hyper::body::to_bytes(wsdl.into_body()).await.unwrap();
+        let wsdl = String::from_utf8(wsdl.to_vec()).unwrap();
+
+        assert!(wsdl.contains(format!("location='{}'", uri).as_str()));
+        assert!(wsdl.contains(format!("operation name='{}'", name).as_str()));
+        assert!(!wsdl.contains(format!("operation name='{}'", other).as_str()));
+    }
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn operations_share_the_service_state() {
+    let options = Options {
+        bind_addr: String::from("127.0.0.1"),
+        namespace: String::from("test"),
+        ..Options::default()
+    };
+
+    let mut service = Service::new_with_opts("127.0.0.1", 0, options);
+    service.set_state(AtomicUsize::new(0));
+
+    service.add_operation(Operation::new("count", Operation::stateful(|calls: &AtomicUsize, _| {
+        let mut response = Response::new();
+        response.responses.insert(String::from("calls"),
+            Part::UnsignedInt(calls.fetch_add(1, Ordering::SeqCst) as u32 + 1));
+        response
+    })));
+
+    service.add_operation(Operation::new("total", Operation::async_stateful(|calls, _| async move {
+        let calls: &AtomicUsize = &calls;
+
+        let mut response = Response::new();
+        response.responses.insert(String::from("calls"),
+            Part::UnsignedInt(calls.load(Ordering::SeqCst) as u32));
+        response
+    })));
+
+    // Asks for state of another type than the service has.
+    service.add_operation(Operation::new("other", Operation::stateful(|_: &String, _| Response::new())));
+
+    let handle = service.start().await.unwrap();
+    let client = Client::new(service.get_uri().as_str(), "test");
+
+    for expected in ["1", "2"] {
+        let response = client.call("count", hashmap(vec![])).await.unwrap();
+        assert_eq!(response.responses["calls"].text(), expected);
+    }
+
+    let response = client.call("total", hashmap(vec![])).await.unwrap();
+    assert_eq!(response.responses["calls"].text(), "2");
+
+    let response = client.call("other", hashmap(vec![])).await.unwrap();
+    assert_eq!(response.get_fault().map(|fault| fault.code()), Some(String::from("Server")));
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn failed_signatures_are_sent_with_500() {
+    let options = Options {
+        bind_addr: String::from("127.0.0.1"),
+        namespace: String::from("test"),
+        ..Options::default()
+    };
+
+    let mut service = Service::new_with_opts("127.0.0.1", 0, options);
+    service.add_middleware(Signature::new().sign_with(CERT, KEY).unwrap());
+
+    // Leaves the signer nothing it can read.
+    service.add_operation(Operation::new("garble", Operation::closure(|_| {
+        let mut response = Response::new();
+        response.add_filter(|_| Ok(String::from("not xml")));
+        response
+    })));
+
+    let handle = service.start().await.unwrap();
+    let client = Client::new(service.get_uri().as_str(), "test");
+
+    let request = HttpRequest::builder()
+        .method(Method::POST)
+        .uri(service.get_uri().as_str())
+        .header("Content-Type", "text/xml; charset=utf-8")
+        .body(Body::from(client.envelope("garble", &hashmap(vec![]))))
+        .unwrap();
+
+    let response = HttpClient::new().request(request).await.unwrap();
+    assert_eq!(response.status().as_u16(), 500);
+
+    let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+    let read = Response::from_xml(std::str::from_utf8(&body).unwrap()).unwrap();
+    assert_eq!(read.get_fault().map(|fault| fault.string()), Some("The response could not be signed"));
+
+    handle.shutdown_timeout(std::time::Duration::from_secs(5)).await.unwrap();
+}
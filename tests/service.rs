@@ -0,0 +1,151 @@
+extern crate webservices;
+
+use std::time::Duration;
+
+use hyper::{ Body, Client, Method, Request };
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use webservices::service::{ Response, Service };
+
+async fn get(uri: &str) -> (u16, String) {
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(uri)
+        .body(Body::empty())
+        .unwrap();
+
+    let response = Client::new().request(request).await.unwrap();
+    let status   = response.status().as_u16();
+    let body     = hyper::body::to_bytes(response.into_body()).await.unwrap();
+
+    (status, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[tokio::test]
+async fn serves_routes_until_shut_down() {
+    let mut service = Service::new_with_bind("127.0.0.1", "127.0.0.1", 0);
+
+    service.add_route("/hello", |request| async move {
+        let name = request.query.get("name").cloned().unwrap_or_default();
+        Response::new(Default::default(), format!("hello {}", name))
+    });
+
+    let handle = service.start().await.unwrap();
+    let base   = format!("http://{}", handle.local_addr());
+
+    assert_eq!(get(format!("{}/hello?name=world", base).as_str()).await, (200, String::from("hello world")));
+    assert_eq!(get(format!("{}/missing", base).as_str()).await.0, 404);
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn answers_other_methods_with_405() {
+    let mut service = Service::new_with_bind("127.0.0.1", "127.0.0.1", 0);
+
+    service.add_route_with_method("POST", "/submit", |_| async move {
+        Response::new(Default::default(), String::from("posted"))
+    });
+
+    service.add_route_with_method("PUT", "/submit", |_| async move {
+        Response::new(Default::default(), String::from("put"))
+    });
+
+    let handle = service.start().await.unwrap();
+
+    let request = Request::builder()
+        .method(Method::GET)
+        .uri(format!("http://{}/submit", handle.local_addr()))
+        .body(Body::empty())
+        .unwrap();
+
+    let response = Client::new().request(request).await.unwrap();
+
+    assert_eq!(response.status().as_u16(), 405);
+    assert_eq!(response.headers()["allow"], "POST, PUT");
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn drains_requests_in_flight_on_shutdown() {
+    let (started, mut running) = mpsc::unbounded_channel();
+
+    let mut service = Service::new_with_bind("127.0.0.1", "127.0.0.1", 0);
+
+    service.add_route("/slow", move |_| {
+        let _ = started.send(());
+
+        async move {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Response::new(Default::default(), String::from("done"))
+        }
+    });
+
+    let handle = service.start().await.unwrap();
+    let addr   = handle.local_addr();
+
+    let request = tokio::spawn(async move {
+        get(format!("http://{}/slow", addr).as_str()).await
+    });
+
+    running.recv().await.unwrap();
+
+    let shutdown = tokio::spawn(handle.shutdown_timeout(Duration::from_secs(5)));
+
+    // The listener goes away as soon as the shutdown is seen, while the
+    // slow request is still being answered.
+    let mut refused = false;
+
+    for _ in 0..50 {
+        if TcpStream::connect(addr).await.is_err() {
+            refused = true;
+            break;
+        }
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+
+    assert!(refused);
+    assert!(!request.is_finished());
+
+    assert_eq!(request.await.unwrap(), (200, String::from("done")));
+    shutdown.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn answers_bodies_over_the_limit_with_413() {
+    let mut service = Service::new_with_bind("127.0.0.1", "127.0.0.1", 0);
+    service.set_max_body_size(16);
+
+    service.add_route("/echo", |request| async move {
+        Response::new(Default::default(), request.content)
+    });
+
+    let handle = service.start().await.unwrap();
+    let uri    = format!("http://{}/echo", handle.local_addr());
+
+    let post = |body: Body| {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(uri.as_str())
+            .body(body)
+            .unwrap();
+
+        Client::new().request(request)
+    };
+
+    let fits = post(Body::from("0123456789abcdef")).await.unwrap();
+    assert_eq!(fits.status().as_u16(), 200);
+
+    // Refused from its Content-Length, and while streaming one without.
+    let declared = post(Body::from("0123456789abcdefg")).await.unwrap();
+    assert_eq!(declared.status().as_u16(), 413);
+
+    let chunks: Vec<Result<&str, std::io::Error>> = vec![Ok("0123456789"), Ok("abcdefg")];
+    let chunked = post(Body::wrap_stream(futures::stream::iter(chunks))).await.unwrap();
+    assert_eq!(chunked.status().as_u16(), 413);
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
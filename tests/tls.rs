@@ -0,0 +1,124 @@
+extern crate webservices;
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::{ Body, Request };
+use hyper::client::conn;
+use rustls_pemfile::Item;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tokio_rustls::rustls::{ Certificate, ClientConfig, PrivateKey, RootCertStore, ServerName };
+
+use webservices::service::{ Handle, Response, Service, TlsConfig };
+
+const CA:         &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tls/ca.pem");
+const SERVER:     &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tls/server.pem");
+const SERVER_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tls/server-key.pem");
+const CLIENT:     &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tls/client.pem");
+const CLIENT_KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/data/tls/client-key.pem");
+
+fn read_pem(path: &str) -> Vec<Item> {
+    let mut reader = BufReader::new(File::open(path).unwrap());
+    rustls_pemfile::read_all(&mut reader).unwrap()
+}
+
+fn certificates(path: &str) -> Vec<Certificate> {
+    read_pem(path).into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(Certificate(der)),
+            _ => None,
+        })
+        .collect()
+}
+
+fn private_key(path: &str) -> PrivateKey {
+    read_pem(path).into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(der) | Item::RSAKey(der) => Some(PrivateKey(der)),
+            _ => None,
+        })
+        .unwrap()
+}
+
+/// An HTTPS server answering `/hello` with how many certificates the
+/// client presented.
+async fn start(tls: TlsConfig) -> (Handle, String) {
+    let mut service = Service::new_with_bind("localhost", "127.0.0.1", 0);
+    service.set_tls(tls);
+
+    service.add_route("/hello", |request| async move {
+        let body = format!("hello, {} certificates", request.peer_certificates.len());
+        Response::new(Default::default(), body)
+    });
+
+    let handle = service.start().await.unwrap();
+    let uri    = service.get_uri();
+
+    (handle, uri)
+}
+
+/// GETs `/hello` over TLS, trusting the test CA and presenting the test
+/// client certificate if `with_cert`.
+async fn get(addr: SocketAddr, with_cert: bool)
+    -> Result<(u16, String), Box<dyn Error + Send + Sync>>
+{
+    let mut roots = RootCertStore::empty();
+
+    for cert in certificates(CA) {
+        roots.add(&cert)?;
+    }
+
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+
+    let config = if with_cert {
+        builder.with_single_cert(certificates(CLIENT), private_key(CLIENT_KEY))?
+    } else {
+        builder.with_no_client_auth()
+    };
+
+    let stream = TcpStream::connect(addr).await?;
+    let stream = TlsConnector::from(Arc::new(config))
+        .connect(ServerName::try_from("localhost")?, stream).await?;
+
+    let (mut sender, connection) = conn::handshake(stream).await?;
+    tokio::spawn(connection);
+
+    let request = Request::get("/hello")
+        .header("host", "localhost")
+        .body(Body::empty())?;
+
+    let response = sender.send_request(request).await?;
+    let status   = response.status().as_u16();
+    let body     = hyper::body::to_bytes(response.into_body()).await?;
+
+    Ok((status, String::from_utf8(body.to_vec())?))
+}
+
+#[tokio::test]
+async fn serves_https() {
+    let (handle, uri) = start(TlsConfig::new(SERVER, SERVER_KEY)).await;
+
+    assert!(uri.starts_with("https://localhost:"));
+    assert_eq!(get(handle.local_addr(), false).await.unwrap(), (200, String::from("hello, 0 certificates")));
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}
+
+#[tokio::test]
+async fn requires_client_certificates() {
+    let tls = TlsConfig::new(SERVER, SERVER_KEY).require_client_cert(CA);
+    let (handle, _) = start(tls).await;
+
+    assert!(get(handle.local_addr(), false).await.is_err());
+    assert_eq!(get(handle.local_addr(), true).await.unwrap(), (200, String::from("hello, 1 certificates")));
+
+    handle.shutdown_timeout(Duration::from_secs(5)).await.unwrap();
+}